@@ -0,0 +1,267 @@
+//! Runs the simulation natively, without the browser, printing JSON for scripts to consume
+
+use std::{collections::HashMap, fs, process, str::FromStr};
+
+use serde_json::{json, Value};
+
+use game2::{
+    estimate::{Estimate, Estimator},
+    level::Level,
+    lyapunov::{self, Lyapunov, LYAPUNOV_DURATION},
+    replay::Replay,
+    rng::Rng,
+    rope::Rope,
+    search::{self, SearchOptions},
+    sim::TIME_STEP,
+    trajectory::Trajectory,
+};
+
+const USAGE: &str = "\
+Usage: chaos-theory <command> [arguments]
+
+Levels are given either as a path to a JSON file or as an index of a shipped level.
+
+Commands:
+    simulate <level> [--setup FILE] [--seed N] [--duration S]
+        Does a single headless run and prints how it ended
+    verify <level> <replay>
+        Plays a replay file again and checks that it ends the same way, exits with 1 if it does not
+    estimate <level> [--setup FILE] [--runs N] [--duration S] [--seed N]
+        Estimates the win chance of a setup from jiggled runs and its largest Lyapunov exponent
+    trajectory <level> [--setup FILE | --replay FILE] [--seed N] [--duration S] [--format json|csv]
+        Prints the positions and velocities of all of the points, the angles of the segments,
+        the energy and the target hits on every step of a run, along with how it ended in JSON
+    solve <level | all> [--seed N] [--candidates N] [--refinements N] [--min-win-rate R]
+        Searches for the best setup, exits with 1 if some level is not solvable
+
+A setup file is a JSON list of ropes, each being a list of points starting with the root.
+Without one the initial state of the level is used.";
+
+const DEFAULT_DURATION: f64 = 20.0;
+const DEFAULT_RUNS: usize = 100;
+const DEFAULT_MIN_WIN_RATE: f64 = 0.5;
+
+/// What a command prints
+enum Output {
+    Json(Value),
+    /// Printed as is, for everything that is not JSON
+    Text(String),
+}
+
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+        let mut positional = Vec::new();
+        let mut options = HashMap::new();
+        while let Some(arg) = args.next() {
+            if let Some(name) = arg.strip_prefix("--") {
+                let value = args.next().ok_or_else(|| format!("Missing a value for --{}", name))?;
+                options.insert(name.to_owned(), value);
+            } else {
+                positional.push(arg);
+            }
+        }
+        Ok(Args { positional, options })
+    }
+
+    fn positional(&self, index: usize, what: &str) -> Result<&str, String> {
+        self.positional.get(index)
+            .map(String::as_str)
+            .ok_or_else(|| format!("Missing the {}", what))
+    }
+
+    fn option<T: FromStr>(&self, name: &str, default: T) -> Result<T, String> {
+        match self.options.get(name) {
+            Some(value) => value.parse().map_err(|_| format!("Invalid value for --{}: {}", name, value)),
+            None => Ok(default),
+        }
+    }
+}
+
+fn read(path: &str) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))
+}
+
+fn load_level(spec: &str) -> Result<Level, String> {
+    if let Ok(index) = spec.parse::<usize>() {
+        return Level::campaign()
+            .into_iter()
+            .nth(index)
+            .ok_or_else(|| format!("There is no shipped level {}", index));
+    }
+    Level::from_json(&read(spec)?).map_err(|e| format!("Invalid level {}: {}", spec, e))
+}
+
+/// The setup from the file if given, with the same roots as in the level
+fn load_setup(level: &Level, args: &Args) -> Result<Vec<Rope>, String> {
+    let path = match args.options.get("setup") {
+        Some(path) => path,
+        None => return Ok(level.init_state()),
+    };
+    let ropes: Vec<Rope> = serde_json::from_str(&read(path)?)
+        .map_err(|e| format!("Invalid setup {}: {}", path, e))?;
+    if ropes.len() != level.pendulums.len() {
+        return Err(format!("The setup has {} ropes, but the level has {} pendulums", ropes.len(), level.pendulums.len()));
+    }
+    ropes.into_iter()
+        .zip(&level.pendulums)
+        .enumerate()
+        .map(|(i, (rope, pendulum))| {
+            if rope.root != pendulum.init_state.root {
+                return Err(format!("Rope {} does not start at the root of its pendulum", i));
+            }
+            Ok(rope.with_breakable(pendulum.breakable))
+        })
+        .collect()
+}
+
+fn load_replay(path: &str) -> Result<Replay, String> {
+    serde_json::from_str(&read(path)?).map_err(|e| format!("Invalid replay {}: {}", path, e))
+}
+
+fn estimate_json(estimate: &Estimate) -> Value {
+    json!({
+        "runs": estimate.runs,
+        "wins": estimate.wins,
+        "win_rate": estimate.win_rate(),
+        "mean_bonuses": estimate.mean_bonuses(),
+        "mean_progress": estimate.mean_progress(),
+        "median_win_time": estimate.win_time_quantile(0.5),
+    })
+}
+
+fn simulate(args: &Args) -> Result<Value, String> {
+    let level = load_level(args.positional(0, "level")?)?;
+    let replay = Replay::new(
+        load_setup(&level, args)?,
+        args.option("seed", 0)?,
+        args.option("duration", DEFAULT_DURATION)?,
+    );
+    Ok(serde_json::to_value(replay.play(&level, |_| {})?).unwrap())
+}
+
+fn verify(args: &Args) -> Result<Value, String> {
+    let level = load_level(args.positional(0, "level")?)?;
+    let replay = load_replay(args.positional(1, "replay")?)?;
+    let verification = replay.verify(&level)?;
+    Ok(json!({
+        "ok": verification.is_ok(),
+        "expected": verification.expected,
+        "actual": verification.actual,
+    }))
+}
+
+fn estimate(args: &Args) -> Result<Value, String> {
+    let level = load_level(args.positional(0, "level")?)?;
+    let setup = load_setup(&level, args)?;
+    let exponent = Lyapunov::new(&level, setup.clone(), LYAPUNOV_DURATION).run(&level);
+    let within_limit = lyapunov::exponent_within_limit(&level, exponent);
+    // too chaotic setups never win, same as in the game and in the search
+    let estimate = if within_limit {
+        Estimator::new(
+            setup,
+            args.option("runs", DEFAULT_RUNS)?,
+            args.option("duration", DEFAULT_DURATION)?,
+            Rng::new(args.option("seed", 0)?),
+        ).run(&level)
+    } else {
+        Estimate::default()
+    };
+    let mut output = estimate_json(&estimate);
+    output["lyapunov"] = json!(exponent);
+    output["within_lyapunov_limit"] = json!(within_limit);
+    // the runs are not steered, so on levels with rails the estimate says little
+    output["needs_steering"] = json!(level.has_rails());
+    Ok(output)
+}
+
+fn trajectory(args: &Args) -> Result<Output, String> {
+    let level = load_level(args.positional(0, "level")?)?;
+    let replay = match args.options.get("replay") {
+        Some(path) => load_replay(path)?,
+        None => Replay::new(
+            load_setup(&level, args)?,
+            args.option("seed", 0)?,
+            args.option("duration", DEFAULT_DURATION)?,
+        ),
+    };
+    let mut trajectory = Trajectory::new();
+    let outcome = replay.play(&level, |sim| trajectory.record(sim, &level))?;
+    match args.option("format", String::from("json"))?.as_str() {
+        "json" => Ok(Output::Json(json!({
+            "time_step": TIME_STEP,
+            "steps": trajectory.frames(),
+            "outcome": outcome,
+        }))),
+        "csv" => Ok(Output::Text(trajectory.to_csv())),
+        format => Err(format!("Unknown format '{}', expected json or csv", format)),
+    }
+}
+
+fn solve(args: &Args) -> Result<Value, String> {
+    let spec = args.positional(0, "level")?;
+    let levels = if spec == "all" {
+        Level::campaign()
+    } else {
+        vec![load_level(spec)?]
+    };
+    let defaults = SearchOptions::default();
+    let options = SearchOptions {
+        candidates: args.option("candidates", defaults.candidates)?,
+        refinements: args.option("refinements", defaults.refinements)?,
+        seed: args.option("seed", defaults.seed)?,
+        ..defaults
+    };
+    let min_win_rate = args.option("min-win-rate", DEFAULT_MIN_WIN_RATE)?;
+
+    let reports = levels.iter()
+        .map(|level| {
+            let report = search::search(level, &options);
+            json!({
+                "solvable": report.is_solvable(min_win_rate),
+                "random_win_rate": report.random_win_rate,
+                "setup": report.best.ropes,
+                "estimate": estimate_json(&report.best.estimate),
+            })
+        })
+        .collect::<Vec<_>>();
+    let solvable = reports.iter().all(|report| report["solvable"] == true);
+    Ok(json!({
+        "solvable": solvable,
+        "levels": reports,
+    }))
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let command = args.next().unwrap_or_default();
+    let result = Args::parse(args).and_then(|args| match command.as_str() {
+        "simulate" => simulate(&args).map(Output::Json),
+        "verify" => verify(&args).map(Output::Json),
+        "estimate" => estimate(&args).map(Output::Json),
+        "trajectory" => trajectory(&args),
+        "solve" => solve(&args).map(Output::Json),
+        "help" | "--help" | "-h" => Ok(Output::Text(format!("{}\n", USAGE))),
+        _ => Err(format!("Unknown command '{}'\n\n{}", command, USAGE)),
+    });
+    match result {
+        Ok(Output::Text(text)) => print!("{}", text),
+        Ok(Output::Json(output)) => {
+            println!("{}", output);
+            // verification failures are still valid output, but scripts need to notice them
+            let failed = output.get("ok") == Some(&Value::Bool(false))
+                || output.get("solvable") == Some(&Value::Bool(false));
+            if failed {
+                process::exit(1);
+            }
+        }
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::VecDeque;
+
+use ld_game_engine::V2;
+
+use crate::{
+    level::Level,
+    rng::Rng,
+    rope::Rope,
+    sim::{Control, Simulation, StepResult},
+};
+
+/// How many of the latest positions of the tails are kept for each of the copies
+const TRAIL_LENGTH: usize = 60 * 10;
+
+/// A copy of a run that started almost, but not quite, the same
+#[derive(Debug, Clone)]
+pub struct Twin {
+    pub sim: Simulation,
+    /// Trail of the tail of each of the ropes
+    pub trails: Vec<VecDeque<V2>>,
+    /// The copy blew up and is no longer simulated
+    pub fell_apart: bool,
+}
+
+/// Many copies of a run simulated side by side to show how quickly they separate
+#[derive(Debug, Clone)]
+pub struct Butterfly {
+    pub copies: Vec<Twin>,
+    pub epsilon: f64,
+}
+
+impl Butterfly {
+    /// Each of the copies starts from the given ropes with its
+    /// points moved by up to half of epsilon along each axis
+    pub fn new(level: &Level, ropes: &[Rope], count: usize, epsilon: f64, rng: &mut Rng) -> Self {
+        let copies = (0..count)
+            .map(|_| {
+                let mut ropes = ropes.to_vec();
+                for rope in &mut ropes {
+                    rope.jiggle_by(rng, epsilon);
+                }
+                Twin {
+                    trails: vec![VecDeque::new(); ropes.len()],
+                    sim: Simulation::new(level, ropes),
+                    fell_apart: false,
+                }
+            })
+            .collect();
+        Self { copies, epsilon }
+    }
+
+    pub fn step(&mut self, level: &Level, control: Control) {
+        for copy in self.copies.iter_mut().filter(|c| !c.fell_apart) {
+            for (trail, rope) in copy.trails.iter_mut().zip(&copy.sim.ropes) {
+                trail.push_back(rope.tail());
+                if trail.len() > TRAIL_LENGTH {
+                    trail.pop_front();
+                }
+            }
+            copy.fell_apart = copy.sim.step(level, control) == StepResult::FellApart;
+        }
+    }
+
+    /// The farthest any of the tails of the copies got from the same tail of the reference run
+    pub fn divergence(&self, reference: &Simulation) -> f64 {
+        self.copies.iter()
+            .filter(|c| !c.fell_apart)
+            .flat_map(|c| c.sim.ropes.iter().zip(&reference.ropes))
+            .map(|(rope, reference)| (rope.tail() - reference.tail()).magnitude())
+            .fold(0.0, f64::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::test_levels::double_pendulum_level;
+
+    const EPSILON: f64 = 1e-3;
+
+    #[test]
+    fn copies_start_within_epsilon() {
+        let level = double_pendulum_level();
+        let ropes = level.init_state();
+        let butterfly = Butterfly::new(&level, &ropes, 8, EPSILON, &mut Rng::new(1));
+        assert_eq!(butterfly.copies.len(), 8);
+        for copy in &butterfly.copies {
+            for (rope, reference) in copy.sim.ropes.iter().zip(&ropes) {
+                for (point, reference) in rope.points().iter().zip(reference.points()) {
+                    let offset = point.pos() - reference.pos();
+                    assert!(offset.x.abs() <= EPSILON / 2.0 && offset.y.abs() <= EPSILON / 2.0);
+                }
+            }
+        }
+        assert!(butterfly.divergence(&Simulation::new(&level, ropes)) <= EPSILON);
+    }
+
+    #[test]
+    fn copies_diverge() {
+        let level = double_pendulum_level();
+        let ropes = level.init_state();
+        let mut reference = Simulation::new(&level, ropes.clone());
+        let mut butterfly = Butterfly::new(&level, &ropes, 8, EPSILON, &mut Rng::new(1));
+        for _ in 0..60 * 10 {
+            reference.step(&level, Control::default());
+            butterfly.step(&level, Control::default());
+        }
+        assert!(butterfly.copies.iter().all(|c| !c.fell_apart));
+        assert_eq!(butterfly.copies[0].trails[0].len(), TRAIL_LENGTH);
+        assert!(butterfly.divergence(&reference) > 1000.0 * EPSILON);
+    }
+}
@@ -6,6 +6,52 @@ pub struct StoredData {
     pub passed_tutorial: bool,
     pub sounds_enabled: bool,
     pub music_enabled: bool,
+    #[serde(default)]
+    pub trail_color: TrailColor,
+}
+
+/// What the trails of the runs are coloured by
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrailColor {
+    /// The colour of the pendulum
+    Plain,
+    /// Speed of the tail
+    Speed,
+    /// Kinetic energy of the whole pendulum
+    KineticEnergy,
+    /// Angle of the last segment
+    Angle,
+    /// Time since the start of the run
+    Time,
+}
+
+impl Default for TrailColor {
+    fn default() -> Self {
+        TrailColor::Plain
+    }
+}
+
+impl TrailColor {
+    pub fn next(self) -> Self {
+        match self {
+            TrailColor::Plain => TrailColor::Speed,
+            TrailColor::Speed => TrailColor::KineticEnergy,
+            TrailColor::KineticEnergy => TrailColor::Angle,
+            TrailColor::Angle => TrailColor::Time,
+            TrailColor::Time => TrailColor::Plain,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TrailColor::Plain => "plain",
+            TrailColor::Speed => "speed",
+            TrailColor::KineticEnergy => "kinetic energy",
+            TrailColor::Angle => "angle",
+            TrailColor::Time => "time",
+        }
+    }
 }
 
 impl Default for StoredData {
@@ -14,6 +60,7 @@ impl Default for StoredData {
             passed_tutorial: false,
             sounds_enabled: true,
             music_enabled: true,
+            trail_color: TrailColor::Plain,
         }
     }
 }
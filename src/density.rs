@@ -0,0 +1,152 @@
+use ld_game_engine::{v2, V2};
+
+/// Colour of the least and of the most visited cells
+const COLD: [f64; 3] = [119.0, 52.0, 235.0];
+const HOT: [f64; 3] = [255.0, 223.0, 0.0];
+
+/// How often the trails passed through each of the cells of a grid, accumulated over any number of runs
+#[derive(Debug, Clone)]
+pub struct TrailDensity {
+    /// The top left corner of the grid in world coordinates
+    pub origin: V2,
+    pub cell_size: f64,
+    pub width: usize,
+    pub height: usize,
+    counts: Vec<u32>,
+    max: u32,
+}
+
+impl TrailDensity {
+    /// The grid is centered around the origin of the world
+    pub fn new(size: V2, cell_size: f64) -> Self {
+        let width = (size.x / cell_size).ceil() as usize;
+        let height = (size.y / cell_size).ceil() as usize;
+        Self {
+            origin: -v2![width as f64, height as f64] * cell_size / 2.0,
+            cell_size,
+            width,
+            height,
+            counts: vec![0; width * height],
+            max: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.max == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.counts.iter_mut().for_each(|c| *c = 0);
+        self.max = 0;
+    }
+
+    pub fn add_trail(&mut self, trail: impl IntoIterator<Item = V2>) {
+        let mut prev = None;
+        for pos in trail {
+            if let Some(prev) = prev {
+                self.add_segment(prev, pos);
+            }
+            prev = Some(pos);
+        }
+    }
+
+    /// Counts the cells along the segment, the end is left out so that
+    /// the cells shared by consecutive segments are not counted twice
+    pub fn add_segment(&mut self, from: V2, to: V2) {
+        let samples = ((to - from).magnitude() / self.cell_size * 2.0).ceil().max(1.0) as usize;
+        let mut prev = None;
+        for i in 0..samples {
+            let pos = from + (to - from) * (i as f64 / samples as f64);
+            let cell = self.cell_at(pos);
+            if cell != prev {
+                if let Some(index) = cell {
+                    self.counts[index] += 1;
+                    self.max = self.max.max(self.counts[index]);
+                }
+                prev = cell;
+            }
+        }
+    }
+
+    fn cell_at(&self, pos: V2) -> Option<usize> {
+        let cell = (pos - self.origin) / self.cell_size;
+        if !(cell.x >= 0.0 && cell.y >= 0.0) {
+            return None;
+        }
+        let (x, y) = (cell.x as usize, cell.y as usize);
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some(y * self.width + x)
+    }
+
+    /// RGBA pixels of the grid, one per cell row by row, on a log scale
+    /// so that the rarely visited cells are still visible
+    pub fn to_rgba(&self) -> Vec<u8> {
+        let log_max = (1.0 + self.max as f64).ln().max(f64::MIN_POSITIVE);
+        let mut pixels = Vec::with_capacity(self.counts.len() * 4);
+        for &count in &self.counts {
+            let t = (1.0 + count as f64).ln() / log_max;
+            let channel = |i: usize| (COLD[i] + (HOT[i] - COLD[i]) * t) as u8;
+            pixels.extend_from_slice(&[channel(0), channel(1), channel(2), (t * 255.0) as u8]);
+        }
+        pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(density: &TrailDensity, x: usize, y: usize) -> u32 {
+        density.counts[y * density.width + x]
+    }
+
+    #[test]
+    fn segments_count_each_cell_once() {
+        let mut density = TrailDensity::new(v2![100.0, 100.0], 10.0);
+        assert_eq!(density.origin, v2![-50.0, -50.0]);
+        // ends right at the start of the fourth cell, which is left out
+        density.add_segment(v2![-45.0, -45.0], v2![-20.0, -45.0]);
+        assert_eq!(&density.counts[..5], &[1, 1, 1, 0, 0]);
+        assert_eq!(density.counts.iter().sum::<u32>(), 3);
+    }
+
+    #[test]
+    fn trails_follow_the_rows_and_columns() {
+        let mut density = TrailDensity::new(v2![100.0, 100.0], 10.0);
+        density.add_trail(vec![v2![-45.0, -45.0], v2![-20.0, -45.0], v2![-20.0, -15.0]]);
+        for y in 0..4 {
+            assert_eq!(count(&density, 3, y), 1);
+        }
+        assert_eq!(count(&density, 3, 4), 0);
+        assert_eq!(density.counts.iter().sum::<u32>(), 7);
+    }
+
+    #[test]
+    fn positions_outside_the_grid_are_ignored() {
+        let mut density = TrailDensity::new(v2![100.0, 100.0], 10.0);
+        density.add_segment(v2![-80.0, 0.0], v2![-60.0, 0.0]);
+        density.add_segment(v2![0.0, 60.0], v2![0.0, 80.0]);
+        assert!(density.is_empty());
+
+        density.add_segment(v2![-60.0, 5.0], v2![-40.0, 5.0]);
+        assert_eq!(count(&density, 0, 5), 1);
+        assert_eq!(density.counts.iter().sum::<u32>(), 1);
+    }
+
+    #[test]
+    fn the_most_visited_cell_is_hot() {
+        let mut density = TrailDensity::new(v2![20.0, 10.0], 10.0);
+        for _ in 0..3 {
+            density.add_segment(v2![-5.0, 0.0], v2![-1.0, 0.0]);
+        }
+        let pixels = density.to_rgba();
+        assert_eq!(pixels.len(), 8);
+        assert_eq!(pixels[..4], [255, 223, 0, 255]);
+        assert_eq!(pixels[7], 0);
+
+        density.clear();
+        assert!(density.is_empty());
+    }
+}
@@ -0,0 +1,578 @@
+use std::{borrow::Cow, f64::consts::PI};
+
+use ld_game_engine::{
+    Context,
+    event::Event::{self, KeyUp, MouseDown, MouseMove, MouseUp},
+    GameState,
+    StateTransition,
+    surface::SurfaceContextExt,
+    v2,
+    V2,
+};
+
+use crate::{
+    ChaosTheory,
+    gravity::Uniform,
+    level::{Circle, Level, Target},
+    main_game::{self, MainGame, DANGER_COLOR},
+    rope::Rope,
+};
+
+/// How close to a point or to the edge of a circle the mouse has to be to grab it
+const GRAB_DISTANCE: f64 = 15.0;
+
+/// Targets and red zones can not be shrunk any further than this
+const MIN_RADIUS: f64 = 10.0;
+
+const NEW_TARGET_RADIUS: f64 = 50.0;
+const NEW_TARGET_CLOSED: f64 = 100.0;
+const NEW_RED_ZONE_RADIUS: f64 = 100.0;
+
+const GRAVITY_ANGLE_STEP: f64 = PI / 12.0;
+const GRAVITY_MAGNITUDE_STEP: f64 = 100.0;
+
+const HIGHLIGHT_COLOR: &str = "#ffdf00";
+
+/// What clicking at an empty place adds
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Tool {
+    Targets,
+    RedZones,
+    /// Extends the initial rope of the chosen pendulum
+    Rope,
+}
+
+impl Tool {
+    fn name(self) -> &'static str {
+        match self {
+            Tool::Targets => "targets",
+            Tool::RedZones => "red zones",
+            Tool::Rope => "rope",
+        }
+    }
+}
+
+/// A part of the level that can be dragged around
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Handle {
+    /// Moves the whole initial rope of the pendulum
+    Root(usize),
+    /// A point of the initial rope other than the root
+    Joint(usize, usize),
+    Target(usize),
+    TargetEdge(usize),
+    /// The outer edge of the closed area around the target
+    ClosedEdge(usize),
+    RedZone(usize),
+    RedZoneEdge(usize),
+}
+
+/// The rope through the given points, none if any of its segments would be too short
+fn rope_through(points: &[V2]) -> Option<Rope> {
+    let (root, rest) = points.split_first()?;
+    let mut rope = Rope::new(*root);
+    rest.iter().all(|&point| rope.add(point)).then(|| rope)
+}
+
+/// Makes the levels without writing any code, they can be play-tested
+/// right away and are saved in the same JSON that [Level::from_json] loads
+#[derive(Debug)]
+pub struct Editor {
+    level: Level,
+    tool: Tool,
+    /// The pendulum that the rope tool extends
+    pendulum: usize,
+    mouse: V2,
+    dragging: Option<Handle>,
+    /// Why the level could not be play-tested or saved
+    error: Option<String>,
+    anim_time: f64,
+}
+
+impl Editor {
+    pub fn new(mut level: Level) -> Self {
+        // an edited level is not a part of the campaign anymore
+        level.next_level = None;
+        Self {
+            level,
+            tool: Tool::Targets,
+            pendulum: 0,
+            mouse: v2![0.0, 0.0],
+            dragging: None,
+            error: None,
+            anim_time: 0.0,
+        }
+    }
+
+    fn handle_at(&self, pos: V2) -> Option<Handle> {
+        let near = |point: V2| (point - pos).magnitude() < GRAB_DISTANCE;
+        let on_edge = |circle: &Circle| ((circle.pos - pos).magnitude() - circle.radius).abs() < GRAB_DISTANCE / 2.0;
+
+        for (i, pendulum) in self.level.pendulums.iter().enumerate() {
+            let points = pendulum.init_state.points();
+            if let Some(j) = (1..points.len()).rev().find(|&j| near(points[j].pos())) {
+                return Some(Handle::Joint(i, j));
+            }
+            if near(pendulum.init_state.root) {
+                return Some(Handle::Root(i));
+            }
+        }
+        // the edges go before the insides so that the circles inside of the others can still be resized,
+        // and the closed area is only grabbed from the outside so that it can be grown back from zero
+        for (i, target) in self.level.targets.iter().enumerate() {
+            if on_edge(&target.zone.extend(target.closed)) && !target.zone.contains(pos) {
+                return Some(Handle::ClosedEdge(i));
+            }
+            if on_edge(&target.zone) {
+                return Some(Handle::TargetEdge(i));
+            }
+        }
+        if let Some(i) = self.level.red_zones.iter().position(on_edge) {
+            return Some(Handle::RedZoneEdge(i));
+        }
+        if let Some(i) = self.level.targets.iter().position(|t| t.zone.contains(pos)) {
+            return Some(Handle::Target(i));
+        }
+        self.level.red_zones.iter().position(|z| z.contains(pos)).map(Handle::RedZone)
+    }
+
+    /// The circle that is highlighted when the handle is hovered
+    fn handle_circle(&self, handle: Handle) -> Circle {
+        match handle {
+            Handle::Root(i) => Circle {
+                pos: self.level.pendulums[i].init_state.root,
+                radius: GRAB_DISTANCE + 5.0,
+            },
+            Handle::Joint(i, j) => Circle {
+                pos: self.level.pendulums[i].init_state.points()[j].pos(),
+                radius: GRAB_DISTANCE,
+            },
+            Handle::Target(i) | Handle::TargetEdge(i) => self.level.targets[i].zone.clone(),
+            Handle::ClosedEdge(i) => {
+                let target = &self.level.targets[i];
+                target.zone.extend(target.closed)
+            }
+            Handle::RedZone(i) | Handle::RedZoneEdge(i) => self.level.red_zones[i].clone(),
+        }
+    }
+
+    /// Changes the points of the initial rope, they are kept out of the closed areas except for the root,
+    /// which is where the level puts it, and the change is dropped if any of the segments would end up too short
+    fn edit_rope(&mut self, pendulum: usize, edit: impl FnOnce(&mut Vec<V2>)) -> bool {
+        let mut points: Vec<V2> = self.level.pendulums[pendulum].init_state.points().iter().map(|p| p.pos()).collect();
+        edit(&mut points);
+        for point in points.iter_mut().skip(1) {
+            *point = self.level.constrain(*point);
+        }
+        match rope_through(&points) {
+            Some(edited) => {
+                self.level.pendulums[pendulum].init_state = edited;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adds a new part with the current tool, returns the handle that moves it
+    fn add(&mut self, pos: V2) -> Option<Handle> {
+        match self.tool {
+            Tool::Targets => {
+                self.level.targets.push(Target {
+                    zone: Circle {
+                        pos,
+                        radius: NEW_TARGET_RADIUS,
+                    },
+                    closed: NEW_TARGET_CLOSED,
+                    pendulum: None,
+                });
+                Some(Handle::Target(self.level.targets.len() - 1))
+            }
+            Tool::RedZones => {
+                self.level.red_zones.push(Circle {
+                    pos,
+                    radius: NEW_RED_ZONE_RADIUS,
+                });
+                Some(Handle::RedZone(self.level.red_zones.len() - 1))
+            }
+            Tool::Rope => {
+                let pendulum = self.pendulum;
+                if self.edit_rope(pendulum, |points| points.push(pos)) {
+                    Some(Handle::Joint(pendulum, self.level.pendulums[pendulum].init_state.points().len() - 1))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    fn drag(&mut self, handle: Handle, pos: V2) {
+        let delta = pos - self.mouse;
+        match handle {
+            Handle::Root(i) => {
+                // the rail is in world coordinates, so it has to follow the root
+                if self.edit_rope(i, |points| points.iter_mut().for_each(|p| *p += delta)) {
+                    if let Some(rail) = &mut self.level.pendulums[i].rail {
+                        rail.translate(delta);
+                    }
+                }
+            }
+            Handle::Joint(i, j) => {
+                self.edit_rope(i, |points| points[j] = pos);
+            }
+            Handle::Target(i) => self.level.targets[i].zone.pos += delta,
+            Handle::TargetEdge(i) => {
+                let zone = &mut self.level.targets[i].zone;
+                zone.radius = (zone.pos - pos).magnitude().max(MIN_RADIUS);
+            }
+            Handle::ClosedEdge(i) => {
+                let target = &mut self.level.targets[i];
+                target.closed = ((target.zone.pos - pos).magnitude() - target.zone.radius).max(0.0);
+            }
+            Handle::RedZone(i) => self.level.red_zones[i].pos += delta,
+            Handle::RedZoneEdge(i) => {
+                let zone = &mut self.level.red_zones[i];
+                zone.radius = (zone.pos - pos).magnitude().max(MIN_RADIUS);
+            }
+        }
+    }
+
+    /// Removing a joint of the rope removes the rest of the rope after it as well
+    fn delete(&mut self, pos: V2) {
+        // the handle being dragged could be gone or point at something else afterwards
+        self.dragging = None;
+        match self.handle_at(pos) {
+            Some(Handle::Joint(i, j)) => {
+                self.edit_rope(i, |points| points.truncate(j));
+            }
+            Some(Handle::Target(i)) | Some(Handle::TargetEdge(i)) | Some(Handle::ClosedEdge(i)) => {
+                self.level.targets.remove(i);
+            }
+            Some(Handle::RedZone(i)) | Some(Handle::RedZoneEdge(i)) => {
+                self.level.red_zones.remove(i);
+            }
+            // the pendulums always keep their root
+            Some(Handle::Root(_)) | None => {}
+        }
+    }
+
+    /// Rotates the uniform part of the gravity by the given angle and adds to its magnitude
+    fn edit_gravity(&mut self, rotate: f64, grow: f64) {
+        let accel = match &mut self.level.gravity.uniform {
+            Uniform::Constant { accel } | Uniform::Rotating { accel, .. } | Uniform::Flipping { accel, .. } => accel,
+        };
+        // from the downward vertical, same as the segment angles
+        let angle = accel.x.atan2(accel.y) + rotate;
+        let magnitude = (accel.magnitude() + grow).max(0.0);
+        *accel = v2![angle.sin(), angle.cos()] * magnitude;
+    }
+
+    fn edit_text(&mut self) {
+        let current = self.level.custom_text.as_deref().unwrap_or("");
+        let text = web_sys::window()
+            .and_then(|w| w.prompt_with_message_and_default("Level text, empty for none", current).ok())
+            .flatten();
+        // none when the prompt was cancelled
+        if let Some(text) = text {
+            self.level.custom_text = (!text.is_empty()).then(|| Cow::Owned(text));
+        }
+    }
+
+    fn playtest(&mut self, game: &mut ChaosTheory) -> StateTransition<ChaosTheory> {
+        match self.level.validate() {
+            Ok(()) => {
+                self.error = None;
+                StateTransition::push(MainGame::playtest(self.level.clone(), game))
+            }
+            Err(e) => {
+                self.error = Some(e);
+                StateTransition::None
+            }
+        }
+    }
+
+    /// Replaces the level with a pasted one, in the same JSON that saving downloads
+    fn load(&mut self) {
+        let json = web_sys::window()
+            .and_then(|w| w.prompt_with_message("Level JSON to load").ok())
+            .flatten();
+        // none when the prompt was cancelled
+        if let Some(json) = json {
+            self.load_json(&json);
+        }
+    }
+
+    /// Keeps the current level when the JSON is not a valid one
+    fn load_json(&mut self, json: &str) {
+        match Level::from_json(json) {
+            Ok(level) => {
+                *self = Self {
+                    tool: self.tool,
+                    anim_time: self.anim_time,
+                    ..Self::new(level)
+                };
+            }
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+
+    fn save(&mut self) {
+        if let Err(e) = self.level.validate() {
+            self.error = Some(e);
+            return;
+        }
+        self.error = None;
+        let json = serde_json::to_string_pretty(&self.level).expect("levels are always serializable");
+        main_game::download("level.json", "application/json", &json);
+    }
+}
+
+impl GameState<ChaosTheory> for Editor {
+    fn on_pushed(&mut self, _context: &mut Context<ChaosTheory>) -> StateTransition<ChaosTheory> {
+        StateTransition::None
+    }
+
+    fn on_event(
+        &mut self,
+        event: Event,
+        context: &mut Context<ChaosTheory>,
+    ) -> StateTransition<ChaosTheory> {
+        match event {
+            MouseDown { pos, .. } => {
+                self.mouse = pos;
+                let handle = self.handle_at(pos);
+                self.dragging = handle.or_else(|| self.add(pos));
+                if let Some(Handle::Root(i)) | Some(Handle::Joint(i, _)) = self.dragging {
+                    self.pendulum = i;
+                }
+            }
+            MouseMove { pos, .. } => {
+                if let Some(handle) = self.dragging {
+                    self.drag(handle, pos);
+                }
+                self.mouse = pos;
+            }
+            MouseUp { .. } => self.dragging = None,
+            KeyUp { code: 49, .. } => self.tool = Tool::Targets,
+            KeyUp { code: 50, .. } => self.tool = Tool::RedZones,
+            KeyUp { code: 51, .. } => self.tool = Tool::Rope,
+            KeyUp { code: 8, .. } | KeyUp { code: 46, .. } => self.delete(self.mouse),
+            KeyUp { code: 37, .. } => self.edit_gravity(-GRAVITY_ANGLE_STEP, 0.0),
+            KeyUp { code: 39, .. } => self.edit_gravity(GRAVITY_ANGLE_STEP, 0.0),
+            KeyUp { code: 38, .. } => self.edit_gravity(0.0, GRAVITY_MAGNITUDE_STEP),
+            KeyUp { code: 40, .. } => self.edit_gravity(0.0, -GRAVITY_MAGNITUDE_STEP),
+            KeyUp { code: 84, .. } => self.edit_text(),
+            KeyUp { code: 83, .. } => self.save(),
+            KeyUp { code: 76, .. } => self.load(),
+            KeyUp { code: 32, .. } => return self.playtest(context.game),
+            KeyUp { code: 27, .. } => return StateTransition::Pop,
+            _ => {}
+        }
+        StateTransition::None
+    }
+
+    fn on_update(&mut self, context: &mut Context<ChaosTheory>) -> StateTransition<ChaosTheory> {
+        let size = context.surface().size();
+
+        let scale_fix = 0.666;
+
+        let surface = context.surface().context();
+
+        main_game::draw_background(context, 100.0 * scale_fix);
+
+        surface.scale(scale_fix, scale_fix).unwrap();
+
+        context.game.background.play_unique();
+
+        self.anim_time += context.delta_time();
+
+        main_game::draw_forbidden_zones(&surface, &self.level);
+        main_game::draw_gravity(&surface, &self.level.gravity, self.anim_time, size / 2.0 / scale_fix);
+        // everything the level keeps is shown, even the parts that can only be changed in its JSON
+        for zone in &self.level.force_zones {
+            main_game::draw_shapes(&surface, &main_game::force_zone_shapes(zone, self.anim_time));
+        }
+        main_game::draw_shapes(&surface, &main_game::drive_shapes(&self.level));
+        main_game::draw_shapes(&surface, &main_game::fixture_shapes(&self.level));
+        // nothing is hit in the editor, so the targets are just outlines
+        main_game::draw_shapes(&surface, &main_game::target_shapes(&self.level, &[]));
+
+        for (i, pendulum) in self.level.pendulums.iter().enumerate() {
+            let color = if self.tool == Tool::Rope && i == self.pendulum { HIGHLIGHT_COLOR } else { "white" };
+            main_game::draw_shapes(&surface, &main_game::rope_shapes(&pendulum.init_state, color));
+        }
+
+        if let Some(handle) = self.dragging.or_else(|| self.handle_at(self.mouse)) {
+            let circle = self.handle_circle(handle);
+            surface.stroke_color(HIGHLIGHT_COLOR);
+            surface.fill_color(HIGHLIGHT_COLOR);
+            surface.set_line_width(2.0);
+            surface.circle(circle.pos, circle.radius);
+            // the insides move the whole circle, the edges only resize it
+            if matches!(handle, Handle::Target(_) | Handle::RedZone(_)) {
+                surface.fill_circle(circle.pos, 5.0);
+            }
+        }
+
+        surface.scale(1.0 / scale_fix, 1.0 / scale_fix).unwrap();
+
+        let top = -size.y / 2.0;
+
+        if let Some(title) = &self.level.custom_text {
+            surface.fill_color("white");
+            surface.set_font("1.5rem monospace");
+            surface.fill_text(title, 0.0, top + context.rem_to_px(1.0)).unwrap();
+        }
+
+        surface.fill_color("white");
+        surface.set_font("1.2rem monospace");
+        let text = format!("level editor, adding {} (1, 2 and 3 to change)", self.tool.name());
+        surface.fill_text(&text, 0.0, top + context.rem_to_px(2.5)).unwrap();
+
+        let accel = self.level.gravity.uniform.at(0.0);
+        let text = format!(
+            "gravity {:.0} at {:.0}° from straight down (arrows to change)",
+            accel.magnitude(), accel.x.atan2(accel.y).to_degrees(),
+        );
+        surface.fill_color("gray");
+        surface.fill_text(&text, 0.0, top + context.rem_to_px(3.7)).unwrap();
+
+        if let Some(error) = &self.error {
+            surface.fill_color(DANGER_COLOR);
+            surface.fill_text(error, 0.0, top + context.rem_to_px(4.9)).unwrap();
+        }
+
+        surface.fill_color("gray");
+        surface.fill_text(
+            "drag to move, drag the edges to resize, backspace to delete",
+            0.0, -top - context.rem_to_px(2.2),
+        ).unwrap();
+        surface.fill_text(
+            "space to play-test, s to save, l to load, t to edit the text, esc to go back",
+            0.0, -top - context.rem_to_px(1.0),
+        ).unwrap();
+
+        StateTransition::None
+    }
+
+    fn on_popped(self: Box<Self>, _context: &mut Context<ChaosTheory>) -> StateTransition<ChaosTheory> {
+        // back to whatever the editor was opened from
+        StateTransition::None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::Rail;
+
+    fn points(editor: &Editor, pendulum: usize) -> Vec<V2> {
+        editor.level.pendulums[pendulum].init_state.points().iter().map(|p| p.pos()).collect()
+    }
+
+    fn accel(editor: &Editor) -> V2 {
+        match editor.level.gravity.uniform {
+            Uniform::Constant { accel } | Uniform::Rotating { accel, .. } | Uniform::Flipping { accel, .. } => accel,
+        }
+    }
+
+    #[test]
+    fn handle_at_finds_the_parts_under_the_mouse() {
+        let mut editor = Editor::new(Level::tutorial_level());
+        // the root is inside of the target, but the rope goes first
+        assert_eq!(editor.handle_at(v2![0.0, 0.0]), Some(Handle::Root(0)));
+        assert_eq!(editor.handle_at(v2![5.0, -300.0]), Some(Handle::Joint(0, 1)));
+        assert_eq!(editor.handle_at(v2![0.0, 50.0]), Some(Handle::TargetEdge(0)));
+        assert_eq!(editor.handle_at(v2![0.0, 300.0]), Some(Handle::ClosedEdge(0)));
+        assert_eq!(editor.handle_at(v2![30.0, 0.0]), Some(Handle::Target(0)));
+        assert_eq!(editor.handle_at(v2![1000.0, 1000.0]), None);
+
+        // without a closed area both of the edges are in the same place, the outside one grows it back
+        editor.level.targets[0].closed = 0.0;
+        assert_eq!(editor.handle_at(v2![0.0, 52.0]), Some(Handle::ClosedEdge(0)));
+        assert_eq!(editor.handle_at(v2![0.0, 48.0]), Some(Handle::TargetEdge(0)));
+    }
+
+    #[test]
+    fn edit_rope_keeps_the_points_out_of_the_closed_areas() {
+        let mut editor = Editor::new(Level::tutorial_level());
+        assert!(editor.edit_rope(0, |points| points.push(v2![100.0, 0.0])));
+        assert_eq!(points(&editor, 0).last(), Some(&v2![300.0, 0.0]));
+
+        // the root stays where it is put, even inside of the target
+        assert!(editor.edit_rope(0, |points| points[0] = v2![0.0, 10.0]));
+        assert_eq!(editor.level.pendulums[0].init_state.root, v2![0.0, 10.0]);
+    }
+
+    #[test]
+    fn edit_rope_drops_too_short_segments() {
+        let mut editor = Editor::new(Level::tutorial_level());
+        let before = points(&editor, 0);
+        assert!(!editor.edit_rope(0, |points| points.push(v2![0.0, -301.0])));
+        assert_eq!(points(&editor, 0), before);
+    }
+
+    #[test]
+    fn dragging_the_root_moves_the_rail_along() {
+        let mut level = Level::tutorial_level();
+        level.pendulums[0].rail = Some(Rail::Line {
+            from: v2![-100.0, 0.0],
+            to: v2![100.0, 0.0],
+        });
+        let mut editor = Editor::new(level);
+        editor.mouse = v2![0.0, 0.0];
+        editor.drag(Handle::Root(0), v2![20.0, 0.0]);
+
+        assert_eq!(points(&editor, 0), vec![v2![20.0, 0.0], v2![20.0, -300.0]]);
+        match editor.level.pendulums[0].rail {
+            Some(Rail::Line { from, to }) => {
+                assert_eq!(from, v2![-80.0, 0.0]);
+                assert_eq!(to, v2![120.0, 0.0]);
+            }
+            ref rail => panic!("the rail changed to {:?}", rail),
+        }
+    }
+
+    #[test]
+    fn deleting_a_joint_removes_the_rest_of_the_rope() {
+        let mut editor = Editor::new(Level::tutorial_level());
+        assert!(editor.edit_rope(0, |points| points.extend([v2![300.0, -300.0], v2![400.0, 0.0]])));
+        assert_eq!(points(&editor, 0).len(), 4);
+
+        editor.delete(v2![300.0, -300.0]);
+        assert_eq!(points(&editor, 0).len(), 2);
+
+        // the root always stays
+        editor.delete(v2![0.0, 0.0]);
+        assert_eq!(points(&editor, 0).len(), 2);
+        assert_eq!(editor.level.targets.len(), 1);
+    }
+
+    #[test]
+    fn loading_keeps_the_level_when_the_json_is_invalid() {
+        let mut editor = Editor::new(Level::tutorial_level());
+        editor.load_json("{}");
+        assert!(editor.error.is_some());
+        assert_eq!(editor.level.targets.len(), 1);
+
+        let mut level = Level::tutorial_level();
+        level.targets.clear();
+        editor.load_json(&serde_json::to_string(&level).unwrap());
+        assert_eq!(editor.error, None);
+        assert!(editor.level.targets.is_empty());
+        assert!(editor.level.next_level.is_none());
+    }
+
+    #[test]
+    fn edit_gravity_rotates_from_the_downward_vertical() {
+        let mut editor = Editor::new(Level::tutorial_level());
+        editor.edit_gravity(PI / 2.0, 0.0);
+        assert!((accel(&editor) - v2![1000.0, 0.0]).magnitude() < 1e-9);
+
+        editor.edit_gravity(0.0, 500.0);
+        assert!((accel(&editor) - v2![1500.0, 0.0]).magnitude() < 1e-9);
+
+        // it can be turned off, but not reversed
+        editor.edit_gravity(0.0, -2000.0);
+        assert_eq!(accel(&editor).magnitude(), 0.0);
+    }
+}
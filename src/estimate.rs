@@ -0,0 +1,199 @@
+use crate::{
+    level::Level,
+    rng::Rng,
+    rope::Rope,
+    sim::{Control, Simulation, StepResult},
+};
+
+/// How a setup did over many runs, each jiggled the same way a retry in the game is
+#[derive(Debug, Clone, Default)]
+pub struct Estimate {
+    pub runs: usize,
+    pub wins: usize,
+    total_bonuses: usize,
+    total_progress: f64,
+    /// When each of the won runs was won, in ascending order
+    pub win_times: Vec<f64>,
+}
+
+impl Estimate {
+    pub fn win_rate(&self) -> f64 {
+        if self.runs == 0 {
+            return 0.0;
+        }
+        self.wins as f64 / self.runs as f64
+    }
+
+    /// Bonuses collected by the end of a run, on average over the won runs
+    pub fn mean_bonuses(&self) -> f64 {
+        if self.wins == 0 {
+            return 0.0;
+        }
+        self.total_bonuses as f64 / self.wins as f64
+    }
+
+    /// Fraction of the targets hit by the end of a run, on average over all of the runs
+    pub fn mean_progress(&self) -> f64 {
+        if self.runs == 0 {
+            return 0.0;
+        }
+        self.total_progress / self.runs as f64
+    }
+
+    /// Time by which the given fraction of the won runs were won
+    pub fn win_time_quantile(&self, fraction: f64) -> Option<f64> {
+        if self.win_times.is_empty() {
+            return None;
+        }
+        let index = (fraction.clamp(0.0, 1.0) * (self.win_times.len() - 1) as f64).round() as usize;
+        Some(self.win_times[index])
+    }
+
+    fn record(&mut self, won_at: Option<f64>, bonuses: usize, progress: f64) {
+        self.runs += 1;
+        self.total_progress += progress;
+        if let Some(time) = won_at {
+            self.wins += 1;
+            self.total_bonuses += bonuses;
+            let index = self.win_times.partition_point(|&t| t < time);
+            self.win_times.insert(index, time);
+        }
+    }
+}
+
+/// Runs a setup many times without the player touching anything.
+/// The work can be split into small chunks so that it can be done over several frames
+#[derive(Debug, Clone)]
+pub struct Estimator {
+    setup: Vec<Rope>,
+    runs: usize,
+    duration: f64,
+    rng: Rng,
+    current: Option<(Simulation, Option<f64>)>,
+    estimate: Estimate,
+}
+
+impl Estimator {
+    /// Each of the runs lasts for the given duration in seconds,
+    /// or until it fails
+    pub fn new(setup: Vec<Rope>, runs: usize, duration: f64, rng: Rng) -> Self {
+        Self {
+            setup,
+            runs,
+            duration,
+            rng,
+            current: None,
+            estimate: Estimate::default(),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.estimate.runs >= self.runs
+    }
+
+    /// Fraction of the runs that are finished
+    pub fn progress(&self) -> f64 {
+        if self.runs == 0 {
+            return 1.0;
+        }
+        self.estimate.runs as f64 / self.runs as f64
+    }
+
+    pub fn estimate(&self) -> &Estimate {
+        &self.estimate
+    }
+
+    /// Does at most the given number of simulation steps
+    pub fn advance(&mut self, level: &Level, max_steps: usize) {
+        for _ in 0..max_steps {
+            if self.is_done() {
+                return;
+            }
+            let (sim, won_at) = match &mut self.current {
+                Some(current) => current,
+                None => {
+                    let mut ropes = self.setup.clone();
+                    for rope in &mut ropes {
+                        rope.jiggle(&mut self.rng);
+                    }
+                    self.current.get_or_insert((Simulation::new(level, ropes), None))
+                }
+            };
+
+            let mut finished = sim.step(level, Control::default()) == StepResult::FellApart;
+            if won_at.is_none() {
+                if sim.all_targets_hit() {
+                    *won_at = Some(sim.time);
+                } else if sim.is_broken() {
+                    finished = true;
+                }
+            }
+            if finished {
+                // falling apart fails the run even after a win, same as in the game
+                self.estimate.record(None, 0, sim.progress());
+                self.current = None;
+            } else if sim.time >= self.duration {
+                self.estimate.record(*won_at, sim.bonuses(), sim.progress());
+                self.current = None;
+            }
+        }
+    }
+
+    /// Does all of the runs at once
+    pub fn run(mut self, level: &Level) -> Estimate {
+        while !self.is_done() {
+            self.advance(level, 1024);
+        }
+        self.estimate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregates_the_recorded_runs() {
+        let mut estimate = Estimate::default();
+        estimate.record(Some(3.0), 2, 1.0);
+        estimate.record(None, 0, 0.5);
+        estimate.record(Some(1.0), 0, 1.0);
+        estimate.record(None, 0, 0.0);
+
+        assert_eq!(estimate.runs, 4);
+        assert_eq!(estimate.wins, 2);
+        assert_eq!(estimate.win_rate(), 0.5);
+        assert_eq!(estimate.mean_bonuses(), 1.0);
+        assert_eq!(estimate.mean_progress(), 0.625);
+        assert_eq!(estimate.win_times, vec![1.0, 3.0]);
+        assert_eq!(estimate.win_time_quantile(0.0), Some(1.0));
+        assert_eq!(estimate.win_time_quantile(1.0), Some(3.0));
+    }
+
+    #[test]
+    fn empty_estimate_has_no_wins() {
+        let estimate = Estimate::default();
+        assert_eq!(estimate.win_rate(), 0.0);
+        assert_eq!(estimate.mean_bonuses(), 0.0);
+        assert_eq!(estimate.mean_progress(), 0.0);
+        assert_eq!(estimate.win_time_quantile(0.5), None);
+    }
+
+    #[test]
+    fn estimator_without_runs_is_done() {
+        let estimator = Estimator::new(Vec::new(), 0, 1.0, Rng::new(0));
+        assert!(estimator.is_done());
+        assert_eq!(estimator.progress(), 1.0);
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_estimate() {
+        let level = Level::tutorial_level();
+        let run = || Estimator::new(level.init_state(), 5, 2.0, Rng::new(7)).run(&level);
+        let (a, b) = (run(), run());
+        assert_eq!(a.runs, 5);
+        assert_eq!(a.wins, b.wins);
+        assert_eq!(a.win_times, b.win_times);
+        assert_eq!(a.mean_progress(), b.mean_progress());
+    }
+}
@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+
+use ld_game_engine::V2;
+
+/// Damping coefficients are clamped to at most this, the velocity is damped explicitly once per step,
+/// so with steps as long as a frame anything stronger overshoots, reversing it instead of slowing it down
+pub const MAX_DAMPING: f64 = 30.0;
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Force {
+    Wind { accel: V2 },
+    /// Gusts sampled from a noise field that slowly changes over time
+    Turbulence { strength: f64, scale: f64, speed: f64, seed: u32 },
+    /// Pushes everything around the center, counter-clockwise for negative strength
+    Vortex { strength: f64 },
+    /// Slows everything down proportionally to the velocity,
+    /// the coefficient is clamped to between zero and [MAX_DAMPING]
+    Damping { coefficient: f64 },
+}
+
+/// A circular region of the level that applies a force to any joint inside of it
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct ForceZone {
+    pub pos: V2,
+    pub radius: f64,
+    pub force: Force,
+}
+
+impl ForceZone {
+    pub fn contains(&self, pos: V2) -> bool {
+        (self.pos - pos).magnitude_squared() <= self.radius * self.radius
+    }
+
+    pub fn accel(&self, pos: V2, velocity: V2, time: f64) -> V2 {
+        if !self.contains(pos) {
+            return V2::zeros();
+        }
+        match self.force {
+            Force::Wind { accel } => accel,
+            Force::Turbulence { strength, scale, speed, seed } => {
+                let x = pos.x / scale;
+                let y = pos.y / scale;
+                let z = time * speed;
+                V2::new(noise(x, y, z, seed), noise(x, y, z, seed.wrapping_add(1))) * strength
+            }
+            Force::Vortex { strength } => {
+                let diff = pos - self.pos;
+                let distance = diff.magnitude();
+                if distance == 0.0 {
+                    return V2::zeros();
+                }
+                // strongest at the center, fading out to the edge
+                V2::new(-diff.y, diff.x) / distance * strength * (1.0 - distance / self.radius)
+            }
+            Force::Damping { coefficient } => -velocity * coefficient.clamp(0.0, MAX_DAMPING),
+        }
+    }
+}
+
+/// A pseudo-random value in [-1, 1] for a given lattice point
+fn hash(x: i64, y: i64, z: i64, seed: u32) -> f64 {
+    let mut h = (x as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (y as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+        ^ (z as u64).wrapping_mul(0x1656_67b1_9e37_79f9)
+        ^ seed as u64;
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^= h >> 33;
+    (h >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+}
+
+/// Smoothly interpolated value noise, deterministic for a given seed
+fn noise(x: f64, y: f64, z: f64, seed: u32) -> f64 {
+    fn smooth(t: f64) -> f64 {
+        t * t * (3.0 - 2.0 * t)
+    }
+    fn lerp(a: f64, b: f64, t: f64) -> f64 {
+        a + (b - a) * t
+    }
+    let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+    let (tx, ty, tz) = (smooth(x - x0), smooth(y - y0), smooth(z - z0));
+    let (x0, y0, z0) = (x0 as i64, y0 as i64, z0 as i64);
+
+    let corner = |dx, dy, dz| hash(x0 + dx, y0 + dy, z0 + dz, seed);
+    let plane = |dz| lerp(
+        lerp(corner(0, 0, dz), corner(1, 0, dz), tx),
+        lerp(corner(0, 1, dz), corner(1, 1, dz), tx),
+        ty,
+    );
+    lerp(plane(0), plane(1), tz)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zone(force: Force) -> ForceZone {
+        ForceZone {
+            pos: V2::new(100.0, 0.0),
+            radius: 50.0,
+            force,
+        }
+    }
+
+    #[test]
+    fn only_points_inside_are_affected() {
+        let accel = V2::new(0.0, -500.0);
+        let forces = [
+            Force::Wind { accel },
+            Force::Turbulence { strength: 500.0, scale: 10.0, speed: 1.0, seed: 3 },
+            Force::Vortex { strength: 500.0 },
+            Force::Damping { coefficient: 1.0 },
+        ];
+        let velocity = V2::new(10.0, 20.0);
+        for &force in &forces {
+            let zone = zone(force);
+            assert_eq!(zone.accel(V2::new(151.0, 0.0), velocity, 0.0), V2::zeros());
+            assert_eq!(zone.accel(V2::new(0.0, 0.0), velocity, 0.0), V2::zeros());
+        }
+        assert_eq!(zone(Force::Wind { accel }).accel(V2::new(149.0, 0.0), velocity, 0.0), accel);
+        assert_eq!(zone(Force::Wind { accel }).accel(V2::new(100.0, 50.0), velocity, 0.0), accel);
+    }
+
+    #[test]
+    fn vortex_pushes_around_the_center() {
+        let vortex = zone(Force::Vortex { strength: 500.0 });
+        let pos = V2::new(125.0, 0.0);
+        let accel = vortex.accel(pos, V2::zeros(), 0.0);
+        // clockwise on the screen, where y points down
+        assert_eq!(accel.dot(&(pos - vortex.pos)), 0.0);
+        assert!(accel.y > 0.0);
+        assert!((accel.magnitude() - 250.0).abs() < 1e-9);
+
+        assert_eq!(vortex.accel(vortex.pos, V2::zeros(), 0.0), V2::zeros());
+        let edge = vortex.accel(V2::new(149.0, 0.0), V2::zeros(), 0.0);
+        assert!(edge.magnitude() < accel.magnitude() / 10.0);
+    }
+
+    #[test]
+    fn damping_is_clamped() {
+        let velocity = V2::new(10.0, -20.0);
+        let pos = V2::new(100.0, 0.0);
+        assert_eq!(zone(Force::Damping { coefficient: 2.0 }).accel(pos, velocity, 0.0), -velocity * 2.0);
+        assert_eq!(zone(Force::Damping { coefficient: 1e6 }).accel(pos, velocity, 0.0), -velocity * MAX_DAMPING);
+        assert_eq!(zone(Force::Damping { coefficient: -1.0 }).accel(pos, velocity, 0.0), V2::zeros());
+    }
+
+    #[test]
+    fn turbulence_is_deterministic_and_bounded() {
+        let turbulence = zone(Force::Turbulence { strength: 500.0, scale: 10.0, speed: 1.0, seed: 3 });
+        let other_seed = zone(Force::Turbulence { strength: 500.0, scale: 10.0, speed: 1.0, seed: 4 });
+        let mut differs = false;
+        for i in 0..20 {
+            let pos = V2::new(80.0 + i as f64 * 2.1, -10.0 + i as f64);
+            let time = i as f64 * 0.3;
+            let accel = turbulence.accel(pos, V2::zeros(), time);
+            assert_eq!(accel, turbulence.accel(pos, V2::zeros(), time));
+            assert!(accel.x.abs() <= 500.0 && accel.y.abs() <= 500.0);
+            differs |= accel != other_seed.accel(pos, V2::zeros(), time);
+        }
+        assert!(differs);
+    }
+}
@@ -0,0 +1,189 @@
+use serde::{Deserialize, Serialize};
+
+use ld_game_engine::V2;
+
+/// The part of the gravity that is the same everywhere
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Uniform {
+    Constant { accel: V2 },
+    /// Rotates with the given angular velocity in radians per second
+    Rotating { accel: V2, angular_velocity: f64 },
+    /// Flips to the opposite direction every `interval` seconds
+    Flipping { accel: V2, interval: f64 },
+}
+
+impl Uniform {
+    pub fn at(&self, time: f64) -> V2 {
+        match *self {
+            Uniform::Constant { accel } => accel,
+            Uniform::Rotating { accel, angular_velocity } => {
+                let (sin, cos) = (angular_velocity * time).sin_cos();
+                V2::new(accel.x * cos - accel.y * sin, accel.x * sin + accel.y * cos)
+            }
+            Uniform::Flipping { accel, interval } => {
+                if (time / interval) as u64 % 2 == 0 {
+                    accel
+                } else {
+                    -accel
+                }
+            }
+        }
+    }
+}
+
+/// A point attractor, or a repulsor when the strength is negative
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct Well {
+    pub pos: V2,
+    pub strength: f64,
+    /// Softens the pull close to the center so that it does not explode
+    pub radius: f64,
+}
+
+impl Well {
+    pub fn at(&self, pos: V2) -> V2 {
+        let diff = self.pos - pos;
+        let distance_sq = diff.magnitude_squared() + self.radius * self.radius;
+        diff * (self.strength / (distance_sq * distance_sq.sqrt()))
+    }
+
+    pub fn potential(&self, pos: V2) -> f64 {
+        let distance_sq = (self.pos - pos).magnitude_squared() + self.radius * self.radius;
+        -self.strength / distance_sq.sqrt()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Gravity {
+    pub uniform: Uniform,
+    #[serde(default)]
+    pub wells: Vec<Well>,
+}
+
+impl Gravity {
+    pub fn new(uniform: Uniform) -> Self {
+        Self {
+            uniform,
+            wells: Vec::new(),
+        }
+    }
+
+    pub fn constant(accel: V2) -> Self {
+        Self::new(Uniform::Constant { accel })
+    }
+
+    pub fn with_well(mut self, pos: V2, strength: f64, radius: f64) -> Self {
+        self.wells.push(Well { pos, strength, radius });
+        self
+    }
+
+    /// Potential energy per unit mass at the given position,
+    /// gravity that changes over time has it change as well
+    pub fn potential(&self, pos: V2, time: f64) -> f64 {
+        self.wells.iter()
+            .map(|well| well.potential(pos))
+            .fold(-self.uniform.at(time).dot(&pos), |acc, potential| acc + potential)
+    }
+
+    pub fn at(&self, pos: V2, time: f64) -> V2 {
+        self.wells.iter()
+            .map(|well| well.at(pos))
+            .fold(self.uniform.at(time), |acc, accel| acc + accel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Minus the gradient of the potential, which the force should be equal to
+    fn numeric_force(potential: impl Fn(V2) -> f64, pos: V2) -> V2 {
+        let h = 1e-3;
+        let dx = V2::new(h, 0.0);
+        let dy = V2::new(0.0, h);
+        -V2::new(
+            (potential(pos + dx) - potential(pos - dx)) / (2.0 * h),
+            (potential(pos + dy) - potential(pos - dy)) / (2.0 * h),
+        )
+    }
+
+    fn assert_close(a: V2, b: V2) {
+        assert!((a - b).magnitude() <= 1e-6 * b.magnitude().max(1.0), "{} is not {}", a, b);
+    }
+
+    #[test]
+    fn well_pulls_towards_its_center() {
+        let well = Well {
+            pos: V2::new(100.0, 0.0),
+            strength: 1e6,
+            radius: 0.0,
+        };
+        let accel = well.at(V2::new(0.0, 0.0));
+        assert_close(accel, V2::new(100.0, 0.0));
+
+        let repulsor = Well { strength: -1e6, ..well };
+        assert_close(repulsor.at(V2::new(0.0, 0.0)), -accel);
+    }
+
+    #[test]
+    fn well_pull_falls_off_with_the_square_of_the_distance() {
+        let well = Well {
+            pos: V2::new(0.0, 0.0),
+            strength: 1e6,
+            radius: 0.0,
+        };
+        let near = well.at(V2::new(0.0, 100.0)).magnitude();
+        let far = well.at(V2::new(0.0, 200.0)).magnitude();
+        assert!((near / far - 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn well_radius_softens_the_center() {
+        let well = Well {
+            pos: V2::new(0.0, 0.0),
+            strength: 1e6,
+            radius: 10.0,
+        };
+        assert_eq!(well.at(V2::new(0.0, 0.0)), V2::zeros());
+        assert!(well.potential(V2::new(0.0, 0.0)).is_finite());
+        // far away it is the same as without the radius
+        let pos = V2::new(0.0, 1e4);
+        let hard = Well { radius: 0.0, ..well };
+        assert!((well.at(pos) - hard.at(pos)).magnitude() < 1e-3 * hard.at(pos).magnitude());
+    }
+
+    #[test]
+    fn potential_is_consistent_with_the_force() {
+        let gravity = Gravity::new(Uniform::Rotating {
+            accel: V2::new(0.0, 1000.0),
+            angular_velocity: 1.0,
+        });
+        let gravity = gravity
+            .with_well(V2::new(100.0, 100.0), 1e6, 10.0)
+            .with_well(V2::new(-300.0, 0.0), -5e5, 20.0);
+        for &pos in &[V2::new(0.0, 0.0), V2::new(150.0, -80.0), V2::new(-250.0, 40.0)] {
+            for &time in &[0.0, 0.7] {
+                let force = numeric_force(|pos| gravity.potential(pos, time), pos);
+                assert_close(gravity.at(pos, time), force);
+            }
+        }
+    }
+
+    #[test]
+    fn uniform_changes_over_time() {
+        let rotating = Uniform::Rotating {
+            accel: V2::new(0.0, 1000.0),
+            angular_velocity: std::f64::consts::FRAC_PI_2,
+        };
+        assert_close(rotating.at(1.0), V2::new(-1000.0, 0.0));
+
+        let flipping = Uniform::Flipping {
+            accel: V2::new(0.0, 1000.0),
+            interval: 2.0,
+        };
+        assert_eq!(flipping.at(1.0), V2::new(0.0, 1000.0));
+        assert_eq!(flipping.at(3.0), V2::new(0.0, -1000.0));
+        assert_eq!(flipping.at(5.0), V2::new(0.0, 1000.0));
+    }
+}
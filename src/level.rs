@@ -0,0 +1,722 @@
+use std::borrow::Cow;
+
+use serde::*;
+
+use ld_game_engine::{v2, V2};
+
+use crate::{
+    force::{Force, ForceZone},
+    gravity::{Gravity, Uniform},
+    rope::{Breakable, Collider, Rope, RootMotion},
+};
+
+const TRAIL_COLOR: &str = "#7734eb";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Circle {
+    pub pos: V2,
+    pub radius: f64,
+}
+
+impl Circle {
+    pub fn extend(&self, extra_radius: f64) -> Circle {
+        Self {
+            pos: self.pos,
+            radius: self.radius + extra_radius,
+        }
+    }
+
+    pub fn contains(&self, pos: V2) -> bool {
+        (self.pos - pos).magnitude_squared() <= self.radius * self.radius
+    }
+
+    pub fn project(&self, pos: V2) -> V2 {
+        let diff = pos - self.pos;
+        let magnitude = diff.magnitude();
+        if magnitude == 0.0 {
+            return self.pos + v2![self.radius, 0.0];
+        }
+        self.pos + diff / magnitude * self.radius
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Target {
+    pub zone: Circle,
+    pub closed: f64,
+    /// Index of the pendulum that has to hit this target, any of them if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pendulum: Option<usize>,
+}
+
+impl Target {
+    pub fn accepts(&self, pendulum: usize) -> bool {
+        self.pendulum.map_or(true, |p| p == pendulum)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pendulum {
+    pub init_state: Rope,
+    #[serde(default = "Pendulum::default_color")]
+    pub color: Cow<'static, str>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drive: Option<RootMotion>,
+    /// Lets the player move the root around during the run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rail: Option<Rail>,
+    /// Segments snap when whipped too hard, failing the run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub breakable: Option<Breakable>,
+}
+
+impl Pendulum {
+    pub fn new(init_state: Rope) -> Self {
+        Self {
+            init_state,
+            color: Pendulum::default_color(),
+            drive: None,
+            rail: None,
+            breakable: None,
+        }
+    }
+
+    pub fn with_breakable(mut self, threshold: f64, steps: u32) -> Self {
+        self.breakable = Some(Breakable { threshold, steps });
+        self
+    }
+
+    pub fn with_rail(mut self, from: V2, to: V2) -> Self {
+        self.rail = Some(Rail::Line { from, to });
+        self
+    }
+
+    pub fn with_color(mut self, color: &'static str) -> Self {
+        self.color = Cow::Borrowed(color);
+        self
+    }
+
+    pub fn with_drive(mut self, drive: RootMotion) -> Self {
+        self.drive = Some(drive);
+        self
+    }
+
+    fn default_color() -> Cow<'static, str> {
+        Cow::Borrowed(TRAIL_COLOR)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Rail {
+    Line { from: V2, to: V2 },
+    Area { zone: Circle },
+}
+
+impl Rail {
+    pub fn clamp(&self, pos: V2) -> V2 {
+        match self {
+            Rail::Line { from, to } => {
+                let direction = to - from;
+                let length_sq = direction.magnitude_squared();
+                if length_sq == 0.0 {
+                    return *from;
+                }
+                let t = ((pos - from).dot(&direction) / length_sq).clamp(0.0, 1.0);
+                from + direction * t
+            }
+            Rail::Area { zone } => {
+                if zone.contains(pos) {
+                    pos
+                } else {
+                    zone.project(pos)
+                }
+            }
+        }
+    }
+
+    /// Moves the whole rail, used when the root it belongs to is moved
+    pub fn translate(&mut self, delta: V2) {
+        match self {
+            Rail::Line { from, to } => {
+                *from += delta;
+                *to += delta;
+            }
+            Rail::Area { zone } => zone.pos += delta,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Solver {
+    /// Verlet integration with the constraints relaxed iteratively
+    Relaxation,
+    /// Integrates the actual equations of motion, see [Rope::simulate_exact]
+    Exact,
+}
+
+impl Default for Solver {
+    fn default() -> Self {
+        Solver::Relaxation
+    }
+}
+
+/// What taking back a part of the run does to its score
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rewind {
+    /// The rewound time is added to the time the run took, which is shown as its score
+    CountsTime,
+    /// A run that was rewound can not be won
+    NoScore,
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Rewind::CountsTime
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Level {
+    pub pendulums: Vec<Pendulum>,
+    pub gravity: Gravity,
+    pub targets: Vec<Target>,
+    #[serde(default)]
+    pub red_zones: Vec<Circle>,
+    #[serde(default)]
+    pub obstacles: Vec<Collider>,
+    #[serde(default)]
+    pub force_zones: Vec<ForceZone>,
+    #[serde(default)]
+    pub solver: Solver,
+    /// Corrects the drift of the total energy during the run,
+    /// only makes sense with gravity that does not change over time and without anything
+    /// else adding or taking away energy
+    #[serde(default)]
+    pub conserve_energy: bool,
+    /// The setup only wins if its largest Lyapunov exponent is at most this, see [Lyapunov](crate::lyapunov::Lyapunov)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_lyapunov: Option<f64>,
+    #[serde(default)]
+    pub rewind: Rewind,
+    #[serde(default)]
+    pub tutorial: bool,
+
+    #[serde(default)]
+    pub custom_text: Option<Cow<'static, str>>,
+
+    #[serde(skip)]
+    pub next_level: Option<fn() -> Level>,
+}
+
+/// An empty level with the normal gravity, the levels only set what they have
+impl Default for Level {
+    fn default() -> Self {
+        Self {
+            pendulums: Vec::new(),
+            gravity: Gravity::constant(v2![0.0, 1000.0]),
+            targets: Vec::new(),
+            red_zones: Vec::new(),
+            obstacles: Vec::new(),
+            force_zones: Vec::new(),
+            solver: Solver::default(),
+            conserve_energy: false,
+            max_lyapunov: None,
+            rewind: Rewind::default(),
+            tutorial: false,
+
+            custom_text: None,
+
+            next_level: None,
+        }
+    }
+}
+
+impl Level {
+    pub fn tutorial_level() -> Level {
+        let mut rope = Rope::new([0.0, 0.0].into());
+        rope.add([0.0, -300.0].into());
+        Level {
+            pendulums: vec![Pendulum::new(rope)],
+            gravity: Gravity::constant(v2![0.0, 1000.0]),
+            targets: vec![
+                Target {
+                    zone: Circle {
+                        pos: [0.0, 0.0].into(),
+                        radius: 50.0,
+                    },
+                    closed: 250.0,
+                    pendulum: None,
+                },
+            ],
+            tutorial: true,
+            next_level: Some(Level::second_level),
+            ..Level::default()
+        }
+    }
+
+    pub fn second_level() -> Level {
+        let mut rope = Rope::new([0.0, 0.0].into());
+        rope.add([0.0, -300.0].into());
+        rope.add([10.0, 300.0].into());
+        Level {
+            pendulums: vec![Pendulum::new(rope)],
+            gravity: Gravity::constant(v2![0.0, 1000.0]),
+            targets: vec![
+                Target {
+                    zone: Circle {
+                        pos: [0.0, 0.0].into(),
+                        radius: 50.0,
+                    },
+                    closed: 250.0,
+                    pendulum: None,
+                },
+            ],
+            custom_text: Some("you're not limited to two sticks".into()),
+            next_level: Some(Level::third_level),
+            ..Level::default()
+        }
+    }
+
+    pub fn third_level() -> Level {
+        let mut rope = Rope::new([0.0, 0.0].into());
+        rope.add([0.0, -300.0].into());
+        Level {
+            pendulums: vec![Pendulum::new(rope)],
+            gravity: Gravity::constant(v2![0.0, 1000.0]),
+            targets: vec![
+                Target {
+                    zone: Circle {
+                        pos: [0.0, 0.0].into(),
+                        radius: 50.0,
+                    },
+                    closed: 250.0,
+                    pendulum: None,
+                },
+                Target {
+                    zone: Circle {
+                        pos: [550.0, 0.0].into(),
+                        radius: 100.0,
+                    },
+                    closed: 100.0,
+                    pendulum: None,
+                },
+            ],
+            custom_text: Some("soft retries with 'r' lead to win more often than you'd think".into()),
+            next_level: Some(Level::fourth_level),
+            ..Level::default()
+        }
+    }
+
+    pub fn fourth_level() -> Level {
+        let mut rope = Rope::new([0.0, 0.0].into());
+        rope.add([0.0, -300.0].into());
+        Level {
+            pendulums: vec![Pendulum::new(rope)],
+            gravity: Gravity::constant(v2![0.0, 1000.0]),
+            targets: vec![
+                Target {
+                    zone: Circle {
+                        pos: [-550.0, 0.0].into(),
+                        radius: 100.0,
+                    },
+                    closed: 100.0,
+                    pendulum: None,
+                },
+                Target {
+                    zone: Circle {
+                        pos: [550.0, 0.0].into(),
+                        radius: 100.0,
+                    },
+                    closed: 100.0,
+                    pendulum: None,
+                },
+            ],
+            red_zones: vec![
+                Circle {
+                    pos: [-550.0, -500.0].into(),
+                    radius: 300.0,
+                },
+                Circle {
+                    pos: [550.0, -500.0].into(),
+                    radius: 300.0,
+                },
+                Circle {
+                    pos: [-550.0, 500.0].into(),
+                    radius: 300.0,
+                },
+                Circle {
+                    pos: [550.0, 500.0].into(),
+                    radius: 300.0,
+                },
+            ],
+            custom_text: Some("you can skip this easy level through the settings ->".into()),
+            next_level: Some(Level::fifth_level),
+            ..Level::default()
+        }
+    }
+
+    pub fn fifth_level() -> Level {
+        let mut rope = Rope::new([0.0, 0.0].into());
+        rope.add([0.0, -200.0].into());
+        Level {
+            pendulums: vec![Pendulum::new(rope)],
+            gravity: Gravity::constant(v2![0.0, 1000.0]),
+            targets: vec![
+                Target {
+                    zone: Circle {
+                        pos: [550.0 - 150.0, -500.0 + 150.0].into(),
+                        radius: 50.0,
+                    },
+                    closed: 40.0,
+                    pendulum: None,
+                },
+                Target {
+                    zone: Circle {
+                        pos: [-550.0 + 150.0, 500.0 - 150.0].into(),
+                        radius: 50.0,
+                    },
+                    closed: 40.0,
+                    pendulum: None,
+                },
+                Target {
+                    zone: Circle {
+                        pos: [650.0 - 150.0, 600.0 - 150.0].into(),
+                        radius: 50.0,
+                    },
+                    closed: 40.0,
+                    pendulum: None,
+                },
+            ],
+            red_zones: vec![
+                Circle {
+                    pos: [-650.0 + 150.0, -600.0 + 150.0].into(),
+                    radius: 90.0,
+                }
+            ],
+            next_level: Some(Level::sixth_level),
+            ..Level::default()
+        }
+    }
+
+    pub fn sixth_level() -> Level {
+        let mut rope = Rope::new([0.0, 0.0].into());
+        rope.add([0.0, -200.0].into());
+        Level {
+            pendulums: vec![Pendulum::new(rope)],
+            gravity: Gravity::constant(v2![1000.0 / std::f64::consts::SQRT_2, 1000.0 / std::f64::consts::SQRT_2]),
+            targets: vec![
+                Target {
+                    zone: Circle {
+                        pos: [-500.0 + 150.0, -500.0 + 150.0].into(),
+                        radius: 50.0,
+                    },
+                    closed: 250.0,
+                    pendulum: None,
+                },
+            ],
+            custom_text: Some("watch your step, gravity is weird".into()),
+            ..Level::default()
+        }
+    }
+
+    /// All of the shipped levels in order, following the next level links from the tutorial
+    pub fn campaign() -> Vec<Level> {
+        let mut levels = vec![Level::tutorial_level()];
+        while let Some(next) = levels.last().and_then(|level| level.next_level) {
+            levels.push(next());
+        }
+        levels
+    }
+
+    pub fn accel(&self, pos: V2, velocity: V2, time: f64) -> V2 {
+        self.force_zones.iter()
+            .map(|zone| zone.accel(pos, velocity, time))
+            .fold(self.gravity.at(pos, time), |acc, accel| acc + accel)
+    }
+
+    /// Loads a level from JSON, rejecting the ones that would break the simulation
+    pub fn from_json(json: &str) -> serde_json::Result<Level> {
+        let level: Level = serde_json::from_str(json)?;
+        level.validate().map_err(<serde_json::Error as de::Error>::custom)?;
+        Ok(level)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        fn positive(value: f64, what: &str) -> Result<(), String> {
+            if value.is_finite() && value > 0.0 {
+                Ok(())
+            } else {
+                Err(format!("{} must be positive, got {}", what, value))
+            }
+        }
+        if self.pendulums.is_empty() {
+            return Err("Level has no pendulums".into());
+        }
+        for pendulum in &self.pendulums {
+            match pendulum.drive {
+                Some(RootMotion::Oscillate { frequency, .. }) | Some(RootMotion::Circle { frequency, .. }) => {
+                    positive(frequency, "Root motion frequency")?
+                }
+                Some(RootMotion::Lissajous { frequency, .. }) => {
+                    positive(frequency.x, "Root motion frequency")?;
+                    positive(frequency.y, "Root motion frequency")?;
+                }
+                None => {}
+            }
+            if let Some(Rail::Area { zone }) = &pendulum.rail {
+                positive(zone.radius, "Rail area radius")?;
+            }
+            if let Some(breakable) = &pendulum.breakable {
+                positive(breakable.threshold, "Break threshold")?;
+            }
+        }
+        for target in &self.targets {
+            positive(target.zone.radius, "Target radius")?;
+            if target.closed < 0.0 {
+                return Err(format!("Target closed radius must not be negative, got {}", target.closed));
+            }
+            if let Some(p) = target.pendulum.filter(|&p| p >= self.pendulums.len()) {
+                return Err(format!("Target refers to a missing pendulum {}", p));
+            }
+        }
+        for red_zone in &self.red_zones {
+            positive(red_zone.radius, "Red zone radius")?;
+        }
+        for obstacle in &self.obstacles {
+            positive(obstacle.radius, "Obstacle radius")?;
+        }
+        for zone in &self.force_zones {
+            positive(zone.radius, "Force zone radius")?;
+            if let Force::Turbulence { scale, .. } = zone.force {
+                positive(scale, "Turbulence scale")?;
+            }
+        }
+        if let Uniform::Flipping { interval, .. } = self.gravity.uniform {
+            positive(interval, "Gravity flip interval")?;
+        }
+        for well in &self.gravity.wells {
+            positive(well.radius, "Gravity well radius")?;
+        }
+        // force zones are fine, they go through the same acceleration as the gravity
+        if let Solver::Exact = self.solver {
+            if self.pendulums.iter().any(|p| p.drive.is_some() || p.rail.is_some()) {
+                return Err("The exact solver needs the roots to be fixed, without drives or rails".into());
+            }
+            if self.pendulums.iter().any(|p| p.breakable.is_some()) {
+                return Err("The exact solver does not break segments".into());
+            }
+            if !self.obstacles.is_empty() {
+                return Err("The exact solver does not collide with obstacles".into());
+            }
+        }
+        if self.conserve_energy {
+            if !matches!(self.gravity.uniform, Uniform::Constant { .. }) {
+                return Err("Conserving the energy needs gravity that does not change over time".into());
+            }
+            if !self.force_zones.is_empty() {
+                return Err("Conserving the energy does not work with force zones".into());
+            }
+        }
+        // the roots may start anywhere, only the rest of the setup is kept out of the closed areas
+        for pendulum in &self.pendulums {
+            for point in &pendulum.init_state.points()[1..] {
+                let pos = point.pos();
+                if (self.constrain(pos) - pos).magnitude() > 1e-6 {
+                    return Err(format!("Setup point ({:.0}, {:.0}) is inside a closed area", pos.x, pos.y));
+                }
+            }
+        }
+        if let Some(max) = self.max_lyapunov.filter(|max| !max.is_finite()) {
+            return Err(format!("Max Lyapunov exponent must be finite, got {}", max));
+        }
+        Ok(())
+    }
+
+    /// Moves a point of the setup out of the areas where the player can not put it
+    pub fn constrain(&self, pos: V2) -> V2 {
+        for red_zone in &self.red_zones {
+            if red_zone.contains(pos) {
+                return red_zone.project(pos);
+            }
+        }
+        for target in &self.targets {
+            let constraint = target.zone.extend(target.closed);
+            if constraint.contains(pos) {
+                return constraint.project(pos);
+            }
+        }
+        for obstacle in &self.obstacles {
+            let constraint = Circle {
+                pos: obstacle.pos,
+                radius: obstacle.radius,
+            };
+            if constraint.contains(pos) {
+                return constraint.project(pos);
+            }
+        }
+        pos
+    }
+
+    /// Some of the roots are steered by the player during the run
+    pub fn has_rails(&self) -> bool {
+        self.pendulums.iter().any(|p| p.rail.is_some())
+    }
+
+    pub fn init_state(&self) -> Vec<Rope> {
+        self.pendulums.iter()
+            .map(|p| p.init_state.clone().with_breakable(p.breakable))
+            .collect()
+    }
+}
+
+/// Small levels for the tests of the other modules, so that they all run on the same ones
+#[cfg(test)]
+pub(crate) mod test_levels {
+    use super::*;
+
+    /// A horizontal pendulum that swings down through a target right below its root
+    pub fn swinging_level() -> Level {
+        let mut rope = Rope::new(v2![0.0, 0.0]);
+        rope.add(v2![100.0, 0.0]);
+        let mut level = Level::tutorial_level();
+        level.pendulums = vec![Pendulum::new(rope)];
+        level.targets = vec![Target {
+            zone: Circle {
+                pos: v2![0.0, 100.0],
+                radius: 20.0,
+            },
+            closed: 0.0,
+            pendulum: None,
+        }];
+        level
+    }
+
+    /// Held up horizontally with the lower segment pointing up, so that it swings chaotically
+    pub fn double_pendulum() -> Rope {
+        let mut rope = Rope::new(v2![0.0, 0.0]);
+        rope.add(v2![100.0, 0.0]);
+        rope.add(v2![100.0, -100.0]);
+        rope
+    }
+
+    /// The [double_pendulum] with nothing to hit, on the exact solver since the relaxation damps the chaos
+    pub fn double_pendulum_level() -> Level {
+        let mut level = Level::tutorial_level();
+        level.pendulums = vec![Pendulum::new(double_pendulum())];
+        level.targets.clear();
+        level.solver = Solver::Exact;
+        level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn campaign_levels_are_valid() {
+        for (i, level) in Level::campaign().iter().enumerate() {
+            assert_eq!(level.validate(), Ok(()), "level {}", i);
+        }
+    }
+
+    #[test]
+    fn validate_rejects_broken_levels() {
+        let mut level = Level::tutorial_level();
+        level.pendulums.clear();
+        assert!(level.validate().is_err());
+
+        let mut level = Level::tutorial_level();
+        level.targets[0].zone.radius = -1.0;
+        assert!(level.validate().is_err());
+
+        let mut level = Level::tutorial_level();
+        level.targets[0].pendulum = Some(1);
+        assert!(level.validate().is_err());
+
+        let mut level = Level::tutorial_level();
+        level.gravity = Gravity::new(Uniform::Flipping {
+            accel: v2![0.0, 1000.0],
+            interval: f64::NAN,
+        });
+        assert!(level.validate().is_err());
+
+        let mut level = Level::tutorial_level();
+        let tail = level.pendulums[0].init_state.tail();
+        level.red_zones.push(Circle {
+            pos: tail,
+            radius: 50.0,
+        });
+        assert!(level.validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_energy_conservation_with_changing_energy() {
+        let mut level = Level::tutorial_level();
+        level.conserve_energy = true;
+        assert_eq!(level.validate(), Ok(()));
+
+        let mut level = Level::tutorial_level();
+        level.conserve_energy = true;
+        level.gravity = Gravity::new(Uniform::Flipping {
+            accel: v2![0.0, 1000.0],
+            interval: 2.0,
+        });
+        assert!(level.validate().is_err());
+
+        let mut level = Level::tutorial_level();
+        level.conserve_energy = true;
+        level.force_zones.push(ForceZone {
+            pos: v2![0.0, 0.0],
+            radius: 100.0,
+            force: Force::Damping { coefficient: 1.0 },
+        });
+        assert!(level.validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_what_the_exact_solver_ignores() {
+        let mut level = Level::tutorial_level();
+        level.solver = Solver::Exact;
+        assert_eq!(level.validate(), Ok(()));
+
+        let mut level = Level::tutorial_level();
+        level.solver = Solver::Exact;
+        level.pendulums[0].rail = Some(Rail::Line {
+            from: v2![-100.0, 0.0],
+            to: v2![100.0, 0.0],
+        });
+        assert!(level.validate().is_err());
+
+        let mut level = Level::tutorial_level();
+        level.solver = Solver::Exact;
+        level.pendulums[0].breakable = Some(Breakable {
+            threshold: 1000.0,
+            steps: 10,
+        });
+        assert!(level.validate().is_err());
+
+        let mut level = Level::tutorial_level();
+        level.solver = Solver::Exact;
+        level.obstacles.push(Collider {
+            pos: v2![500.0, 500.0],
+            radius: 50.0,
+            restitution: 0.5,
+        });
+        assert!(level.validate().is_err());
+    }
+
+    #[test]
+    fn from_json_validates() {
+        let mut level = Level::tutorial_level();
+        level.targets[0].zone.radius = -1.0;
+        let json = serde_json::to_string(&level).unwrap();
+        assert!(Level::from_json(&json).is_err());
+
+        let json = serde_json::to_string(&Level::tutorial_level()).unwrap();
+        assert!(Level::from_json(&json).is_ok());
+    }
+}
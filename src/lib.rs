@@ -15,12 +15,29 @@ use ld_game_engine::{
 };
 use ld_game_engine::util::Bitmap;
 
-use crate::main_game::{Level, MainGame};
+use crate::{level::Level, main_game::MainGame};
 
 pub mod rope;
 pub mod main_game;
 pub mod data;
 pub mod tutorial;
+pub mod gravity;
+pub mod force;
+pub mod level;
+pub mod sim;
+pub mod rng;
+pub mod estimate;
+pub mod butterfly;
+pub mod density;
+pub mod editor;
+pub mod lyapunov;
+pub mod phase;
+pub mod sensitivity;
+pub mod svg;
+pub mod search;
+pub mod replay;
+pub mod rewind;
+pub mod trajectory;
 
 #[derive(Debug)]
 pub struct ChaosTheory {
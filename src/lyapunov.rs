@@ -0,0 +1,184 @@
+use crate::{
+    level::Level,
+    rng::Rng,
+    rope::Rope,
+    sim::{Control, Simulation, StepResult, TIME_STEP},
+};
+
+/// How long the runs are when checking the setup against [Level::max_lyapunov]
+pub const LYAPUNOV_DURATION: f64 = 10.0;
+
+/// How far the twin starts from the reference run
+const INITIAL_SEPARATION: f64 = 1e-6;
+
+/// The twin is pulled back to the initial separation this often, in steps,
+/// before the difference grows so large that it stops being linear
+const RENORMALIZE_EVERY: usize = 10;
+
+/// Estimates the largest Lyapunov exponent of a setup, in 1/s, by following a twin
+/// run that starts very close to it and measuring how fast the two separate.
+/// Same as with the [Estimator](crate::estimate::Estimator) the work can be done over several frames
+#[derive(Debug, Clone)]
+pub struct Lyapunov {
+    reference: Simulation,
+    twin: Simulation,
+    steps: usize,
+    max_steps: usize,
+    log_growth: f64,
+    renormalizations: usize,
+    stopped: bool,
+}
+
+impl Lyapunov {
+    pub fn new(level: &Level, setup: Vec<Rope>, duration: f64) -> Self {
+        // always the same direction of the separation, so that the same setup gets the same score
+        let mut rng = Rng::new(0);
+        let mut twin = setup.clone();
+        for rope in &mut twin {
+            rope.jiggle_by(&mut rng, 1.0);
+        }
+        let reference = Simulation::new(level, setup);
+        let mut twin = Simulation::new(level, twin);
+        let separation = separation(&reference, &twin);
+        if separation > 0.0 {
+            rescale(&mut twin, &reference, INITIAL_SEPARATION / separation);
+        }
+        Self {
+            reference,
+            twin,
+            steps: 0,
+            max_steps: (duration / TIME_STEP).ceil() as usize,
+            log_growth: 0.0,
+            renormalizations: 0,
+            stopped: false,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.stopped || self.steps >= self.max_steps
+    }
+
+    /// Fraction of the steps that are done
+    pub fn progress(&self) -> f64 {
+        if self.is_done() {
+            return 1.0;
+        }
+        self.steps as f64 / self.max_steps as f64
+    }
+
+    /// The average rate of the separation so far, none until the first renormalization
+    /// and for setups that have nothing free to move
+    pub fn exponent(&self) -> Option<f64> {
+        if self.renormalizations == 0 {
+            return None;
+        }
+        Some(self.log_growth / (self.renormalizations * RENORMALIZE_EVERY) as f64 / TIME_STEP)
+    }
+
+    /// Does at most the given number of simulation steps of each of the runs
+    pub fn advance(&mut self, level: &Level, max_steps: usize) {
+        for _ in 0..max_steps {
+            if self.is_done() {
+                return;
+            }
+            let reference = self.reference.step(level, Control::default());
+            let twin = self.twin.step(level, Control::default());
+            if reference == StepResult::FellApart || twin == StepResult::FellApart {
+                // whatever separation it had until now is all that can be measured
+                self.stopped = true;
+                return;
+            }
+            self.steps += 1;
+
+            if self.steps % RENORMALIZE_EVERY == 0 {
+                let separation = separation(&self.reference, &self.twin);
+                if separation == 0.0 {
+                    // the twin did not start apart at all, nothing to measure
+                    self.stopped = true;
+                    return;
+                }
+                self.log_growth += (separation / INITIAL_SEPARATION).ln();
+                self.renormalizations += 1;
+                rescale(&mut self.twin, &self.reference, INITIAL_SEPARATION / separation);
+            }
+        }
+    }
+
+    /// Does all of the steps at once
+    pub fn run(mut self, level: &Level) -> Option<f64> {
+        while !self.is_done() {
+            self.advance(level, 1024);
+        }
+        self.exponent()
+    }
+}
+
+fn separation(a: &Simulation, b: &Simulation) -> f64 {
+    a.ropes.iter()
+        .zip(&b.ropes)
+        .map(|(a, b)| a.separation(b).powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
+
+fn rescale(twin: &mut Simulation, reference: &Simulation, factor: f64) {
+    for (rope, reference) in twin.ropes.iter_mut().zip(&reference.ropes) {
+        rope.rescale_from(reference, factor);
+    }
+}
+
+/// Whether the setup is calm enough for the level, the ones with nothing to measure always are
+pub fn within_limit(level: &Level, setup: Vec<Rope>) -> bool {
+    level.max_lyapunov.is_none()
+        || exponent_within_limit(level, Lyapunov::new(level, setup, LYAPUNOV_DURATION).run(level))
+}
+
+/// Same as [within_limit], for an exponent that is already measured
+pub fn exponent_within_limit(level: &Level, exponent: Option<f64>) -> bool {
+    match (level.max_lyapunov, exponent) {
+        (Some(max), Some(exponent)) => exponent <= max,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ld_game_engine::V2;
+
+    use super::*;
+    use crate::level::Solver;
+
+    /// Released from rest with the given points after the root, on the exact solver,
+    /// since the relaxation damps the separation a lot
+    fn exponent(points: &[V2]) -> Option<f64> {
+        let mut level = Level::tutorial_level();
+        level.solver = Solver::Exact;
+        let mut rope = Rope::new(V2::new(0.0, 0.0));
+        for &point in points {
+            assert!(rope.add(point));
+        }
+        Lyapunov::new(&level, vec![rope], LYAPUNOV_DURATION).run(&level)
+    }
+
+    #[test]
+    fn single_pendulum_is_calm() {
+        let exponent = exponent(&[V2::new(100.0, 0.0)]).unwrap();
+        assert!(exponent < 1.0, "λ = {}", exponent);
+    }
+
+    #[test]
+    fn double_pendulum_is_chaotic() {
+        let exponent = exponent(&[V2::new(100.0, 0.0), V2::new(100.0, -100.0)]).unwrap();
+        assert!(exponent > 1.0, "λ = {}", exponent);
+    }
+
+    #[test]
+    fn nothing_measured_is_within_the_limit() {
+        let mut level = Level::tutorial_level();
+        level.max_lyapunov = Some(0.0);
+        assert!(exponent_within_limit(&level, None));
+        assert!(!exponent_within_limit(&level, Some(1.0)));
+        level.max_lyapunov = None;
+        assert!(exponent_within_limit(&level, Some(1.0)));
+    }
+}
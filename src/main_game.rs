@@ -2,14 +2,17 @@ use std::{
     borrow::Cow,
     collections::VecDeque,
     f64::consts::TAU,
+    fmt,
 };
 
-use serde::*;
+use js_sys::Array;
+use wasm_bindgen::{Clamped, JsCast, JsValue};
+use web_sys::{Blob, BlobPropertyBag, CanvasRenderingContext2d, HtmlAnchorElement, HtmlCanvasElement, ImageData, Url};
 
 use ld_game_engine::{
     Context,
     event::{
-        Event::{self, KeyUp, MouseDown, MouseMove, MouseUp},
+        Event::{self, KeyDown, KeyUp, MouseDown, MouseMove, MouseUp},
         KeyMeta,
     },
     event::MouseButton,
@@ -23,36 +26,129 @@ use ld_game_engine::{
 
 use crate::{
     BUTTON_COLOR,
+    butterfly::Butterfly,
     ChaosTheory,
-    data::StoredData,
+    data::{StoredData, TrailColor},
+    density::TrailDensity,
+    editor::Editor,
+    estimate::Estimator,
+    force::{Force, ForceZone},
+    gravity::Gravity,
     HOVER_COLOR,
-    rope::Rope,
+    level::{Level, Rail, Rewind},
+    lyapunov::{self, Lyapunov},
+    phase::PhasePlot,
+    replay::{Outcome, Replay},
+    rng::Rng,
+    rewind::History,
+    rope::{Energy, Rope},
+    svg::{Style, Svg},
+    sensitivity::{Cell, Metric, Sensitivity},
+    sim::{Control, Simulation, StepResult, TIME_STEP},
+    trajectory::Trajectory,
     tutorial::Tutorial,
 };
 
 #[derive(Debug)]
 enum SimStatus {
     Setup,
-    Running { setup: Rope },
-    Paused { setup: Rope },
+    Running { setup: Vec<Rope> },
+    Paused { setup: Vec<Rope> },
 }
 
 #[derive(Debug)]
 enum WinStatus {
     NotYet,
-    Won { bonuses: usize },
+    Won {
+        bonuses: usize,
+        /// How long the run took until the win, including the rewound time
+        time: f64,
+    },
+    Failed { reason: FailReason },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum FailReason {
+    Snapped,
+    FellApart,
+    /// All of the targets were hit, but the setup is over the Lyapunov exponent limit
+    TooChaotic,
+}
+
+impl fmt::Display for FailReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FailReason::Snapped => "Snapped",
+            FailReason::FellApart => "Fell apart",
+            FailReason::TooChaotic => "Too chaotic",
+        })
+    }
+}
+
+/// A single sample of a trail, with everything it can be coloured by
+#[derive(Debug, Copy, Clone)]
+struct TrailPoint {
+    pos: V2,
+    time: f64,
+    /// Angle of the last segment, see [Rope::segment_phase]
+    angle: f64,
+    /// Kinetic energy of the whole pendulum, with unit masses at the joints
+    kinetic: f64,
+}
+
+impl TrailPoint {
+    fn of(rope: &Rope, time: f64) -> Self {
+        let attached = rope.constraints.iter().take_while(|c| !c.broken).count();
+        Self {
+            pos: rope.tail(),
+            time,
+            angle: attached.checked_sub(1).map_or(0.0, |last| rope.segment_phase(last, TIME_STEP).0),
+            kinetic: rope.energy(|_| 0.0, TIME_STEP).kinetic,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct MainGame {
-    rope: Rope,
+    sim: Simulation,
     sim_status: SimStatus,
     win_status: WinStatus,
-    prev_trails: VecDeque<VecDeque<V2>>,
-    trail: VecDeque<V2>,
-    creating: Option<V2>,
-    touched_targets: Box<[usize]>,
-    touching_target: Option<usize>,
+    prev_trails: VecDeque<VecDeque<TrailPoint>>,
+    trail_density: TrailDensity,
+    density_shown: bool,
+    /// The density drawn into an offscreen canvas, none if the density changed since it was drawn
+    density_canvas: Option<HtmlCanvasElement>,
+    trails: Vec<VecDeque<TrailPoint>>,
+    trajectory: Trajectory,
+    creating: Option<(usize, V2)>,
+    accumulator: f64,
+    history: History,
+    /// The rewind key is held down
+    rewinding: bool,
+    /// Total time taken back during the current run
+    rewound: f64,
+    /// Index into [SPEEDS]
+    speed: usize,
+    anim_time: f64,
+    energy_history: VecDeque<Energy>,
+    energy_shown: bool,
+    phase: PhasePlot,
+    phase_shown: bool,
+    control: Control,
+    rng: Rng,
+    /// The current run, recorded so that it can be downloaded and played again
+    replay: Option<Replay>,
+    estimator: Option<Estimator>,
+    /// Measured during the setup and then during its runs, until it is done
+    lyapunov: Option<Lyapunov>,
+    /// The step that hit all of the targets before the measurement was done,
+    /// the run is held there until it is and the win is decided
+    pending_win: Option<StepResult>,
+    butterfly_mode: bool,
+    butterfly_count: usize,
+    butterfly_epsilon: f64,
+    butterfly: Option<Butterfly>,
+    sensitivity: Option<Sensitivity>,
 
     next_level_button: Button,
 
@@ -63,6 +159,18 @@ pub struct MainGame {
     music_button: Button,
     skip_button: Button,
     tutorial_button: Button,
+    trail_color_button: Button,
+    csv_button: Button,
+    json_button: Button,
+    replay_button: Button,
+    svg_button: Button,
+    png_button: Button,
+    /// The canvas is saved once the world is drawn on the next frame
+    png_requested: bool,
+    edit_button: Button,
+
+    /// Started from the [Editor], which it goes back to instead of the next level
+    playtest: bool,
 
     tutorial: Option<Tutorial>,
 
@@ -70,256 +178,40 @@ pub struct MainGame {
     next_level: Option<Level>,
 }
 
-#[derive(Debug, Deserialize)]
-struct Circle {
-    pos: V2,
-    radius: f64,
-}
-
-impl Circle {
-    pub fn extend(&self, extra_radius: f64) -> Circle {
-        Self {
-            pos: self.pos,
-            radius: self.radius + extra_radius,
-        }
-    }
-
-    pub fn contains(&self, pos: V2) -> bool {
-        (self.pos - pos).magnitude_squared() <= self.radius * self.radius
-    }
-
-    pub fn project(&self, pos: V2) -> V2 {
-        self.pos + (pos - self.pos).normalize() * self.radius
-    }
-}
-
-#[derive(Debug, Deserialize)]
-struct Target {
-    zone: Circle,
-    closed: f64,
-}
-
-#[derive(Debug)]
-pub struct Level {
-    init_state: Rope,
-    gravity: V2,
-    targets: Vec<Target>,
-    red_zones: Vec<Circle>,
-    tutorial: bool,
-
-    custom_text: Option<&'static str>,
-
-    next_level: Option<fn() -> Level>,
-}
-
-impl Level {
-    pub fn tutorial_level() -> Level {
-        let mut rope = Rope::new([0.0, 0.0].into());
-        rope.add([0.0, -300.0].into());
-        Level {
-            init_state: rope,
-            gravity: v2![0.0, 1000.0],
-            targets: vec![
-                Target {
-                    zone: Circle {
-                        pos: [0.0, 0.0].into(),
-                        radius: 50.0,
-                    },
-                    closed: 250.0,
-                },
-            ],
-            red_zones: vec![],
-            tutorial: true,
-
-            custom_text: None,
-
-            next_level: Some(Level::second_level),
-        }
-    }
-
-    pub fn second_level() -> Level {
-        let mut rope = Rope::new([0.0, 0.0].into());
-        rope.add([0.0, -300.0].into());
-        rope.add([10.0, 300.0].into());
-        Level {
-            init_state: rope,
-            gravity: v2![0.0, 1000.0],
-            targets: vec![
-                Target {
-                    zone: Circle {
-                        pos: [0.0, 0.0].into(),
-                        radius: 50.0,
-                    },
-                    closed: 250.0,
-                },
-            ],
-            red_zones: vec![],
-            tutorial: false,
-
-            custom_text: Some("you're not limited to two sticks"),
-
-            next_level: Some(Level::third_level),
-        }
-    }
-
-    pub fn third_level() -> Level {
-        let mut rope = Rope::new([0.0, 0.0].into());
-        rope.add([0.0, -300.0].into());
-        Level {
-            init_state: rope,
-            gravity: v2![0.0, 1000.0],
-            targets: vec![
-                Target {
-                    zone: Circle {
-                        pos: [0.0, 0.0].into(),
-                        radius: 50.0,
-                    },
-                    closed: 250.0,
-                },
-                Target {
-                    zone: Circle {
-                        pos: [550.0, 0.0].into(),
-                        radius: 100.0,
-                    },
-                    closed: 100.0,
-                },
-            ],
-            red_zones: vec![],
-            tutorial: false,
-
-            custom_text: Some("soft retries with 'r' lead to win more often than you'd think"),
-
-            next_level: Some(Level::fourth_level),
-        }
-    }
-
-    pub fn fourth_level() -> Level {
-        let mut rope = Rope::new([0.0, 0.0].into());
-        rope.add([0.0, -300.0].into());
-        Level {
-            init_state: rope,
-            gravity: v2![0.0, 1000.0],
-            targets: vec![
-                Target {
-                    zone: Circle {
-                        pos: [-550.0, 0.0].into(),
-                        radius: 100.0,
-                    },
-                    closed: 100.0,
-                },
-                Target {
-                    zone: Circle {
-                        pos: [550.0, 0.0].into(),
-                        radius: 100.0,
-                    },
-                    closed: 100.0,
-                },
-            ],
-            red_zones: vec![
-                Circle {
-                    pos: [-550.0, -500.0].into(),
-                    radius: 300.0,
-                },
-                Circle {
-                    pos: [550.0, -500.0].into(),
-                    radius: 300.0,
-                },
-                Circle {
-                    pos: [-550.0, 500.0].into(),
-                    radius: 300.0,
-                },
-                Circle {
-                    pos: [550.0, 500.0].into(),
-                    radius: 300.0,
-                },
-            ],
-            tutorial: false,
-
-            custom_text: Some("you can skip this easy level through the settings ->"),
-
-            next_level: Some(Level::fifth_level),
-        }
-    }
-
-    pub fn fifth_level() -> Level {
-        let mut rope = Rope::new([0.0, 0.0].into());
-        rope.add([0.0, -200.0].into());
-        Level {
-            init_state: rope,
-            gravity: v2![0.0, 1000.0],
-            targets: vec![
-                Target {
-                    zone: Circle {
-                        pos: [550.0 - 150.0, -500.0 + 150.0].into(),
-                        radius: 50.0,
-                    },
-                    closed: 40.0,
-                },
-                Target {
-                    zone: Circle {
-                        pos: [-550.0 + 150.0, 500.0 - 150.0].into(),
-                        radius: 50.0,
-                    },
-                    closed: 40.0,
-                },
-                Target {
-                    zone: Circle {
-                        pos: [650.0 - 150.0, 600.0 - 150.0].into(),
-                        radius: 50.0,
-                    },
-                    closed: 40.0,
-                },
-            ],
-            red_zones: vec![
-                Circle {
-                    pos: [-650.0 + 150.0, -600.0 + 150.0].into(),
-                    radius: 90.0,
-                }
-            ],
-            tutorial: false,
-
-            custom_text: None,
-
-            next_level: Some(Level::sixth_level),
-        }
-    }
-
-    pub fn sixth_level() -> Level {
-        let mut rope = Rope::new([0.0, 0.0].into());
-        rope.add([0.0, -200.0].into());
-        Level {
-            init_state: rope,
-            gravity: v2![1000.0 / std::f64::consts::SQRT_2, 1000.0 / std::f64::consts::SQRT_2],
-            targets: vec![
-                Target {
-                    zone: Circle {
-                        pos: [-500.0 + 150.0, -500.0 + 150.0].into(),
-                        radius: 50.0,
-                    },
-                    closed: 250.0,
-                },
-            ],
-            red_zones: vec![],
-            tutorial: false,
-
-            custom_text: Some("watch your step, gravity is weird"),
-
-            next_level: None,
-        }
-    }
-}
-
 impl MainGame {
     pub fn new(level: Level, game: &mut ChaosTheory) -> Self {
         Self {
-            rope: level.init_state.clone(),
+            sim: Simulation::new(&level, level.init_state()),
             sim_status: SimStatus::Setup,
             win_status: WinStatus::NotYet,
             prev_trails: VecDeque::new(),
-            trail: VecDeque::new(),
+            trail_density: TrailDensity::new(DENSITY_SIZE.into(), DENSITY_CELL_SIZE),
+            density_shown: false,
+            density_canvas: None,
+            trails: vec![VecDeque::new(); level.pendulums.len()],
+            trajectory: Trajectory::with_limit(TRAJECTORY_LIMIT),
             creating: None,
-            touched_targets: vec![0; level.targets.len()].into_boxed_slice(),
-            touching_target: None,
+            accumulator: 0.0,
+            history: History::new(REWIND_LIMIT),
+            rewinding: false,
+            rewound: 0.0,
+            speed: NORMAL_SPEED,
+            anim_time: 0.0,
+            energy_history: VecDeque::new(),
+            energy_shown: false,
+            phase: PhasePlot::default(),
+            phase_shown: false,
+            control: Control::default(),
+            rng: Rng::from_entropy(),
+            replay: None,
+            estimator: None,
+            lyapunov: None,
+            pending_win: None,
+            butterfly_mode: false,
+            butterfly_count: 8,
+            butterfly_epsilon: 1e-3,
+            butterfly: None,
+            sensitivity: None,
 
             next_level_button: game.button(""),
 
@@ -330,6 +222,16 @@ impl MainGame {
             music_button: game.button("").with_size(1.2),
             skip_button: game.button("").with_size(1.2),
             tutorial_button: game.button("").with_size(1.2),
+            trail_color_button: game.button("").with_size(1.2),
+            csv_button: game.button("").with_size(1.2),
+            json_button: game.button("").with_size(1.2),
+            replay_button: game.button("").with_size(1.2),
+            svg_button: game.button("").with_size(1.2),
+            png_button: game.button("").with_size(1.2),
+            png_requested: false,
+            edit_button: game.button("").with_size(1.2),
+
+            playtest: false,
 
             tutorial: level.tutorial.then(|| Tutorial::new(game)),
 
@@ -337,15 +239,70 @@ impl MainGame {
             next_level: None,
         }
     }
+
+    pub fn playtest(level: Level, game: &mut ChaosTheory) -> Self {
+        Self {
+            playtest: true,
+            ..Self::new(level, game)
+        }
+    }
 }
 
+/// How fast the simulation can run compared to the real time
+const SPEEDS: [f64; 8] = [0.1, 0.25, 0.5, 1.0, 2.0, 4.0, 6.0, 8.0];
+const NORMAL_SPEED: usize = 3;
+
+/// How many of the latest steps can be rewound, half a minute of them
+const REWIND_LIMIT: usize = 60 * 30;
+
+/// Steps taken back on each frame while the rewind key is held
+const REWIND_SPEED: usize = 2;
+
+/// How many jiggled runs of a setup the win chance is estimated from
+const ESTIMATE_RUNS: usize = 100;
+
+/// How long each of the runs is, in seconds
+const ESTIMATE_DURATION: f64 = 20.0;
+
+/// Milliseconds per frame spent on the estimate, so that the game does not stutter
+const ESTIMATE_BUDGET: f64 = 4.0;
+
+/// How many of the latest steps of a run can be downloaded, a minute of it
+const TRAJECTORY_LIMIT: usize = 60 * 60;
+
+const MAX_BUTTERFLY_COUNT: usize = 32;
+
+/// Area of the world the density of the previous trails is accumulated over
+const DENSITY_SIZE: [f64; 2] = [3200.0, 2000.0];
+
+const DENSITY_CELL_SIZE: f64 = 8.0;
+
+/// Cells along each of the sides of the sensitivity heatmap
+const HEATMAP_RESOLUTION: usize = 48;
+
+/// How long each of the cells of the heatmap is simulated for, in seconds
+const HEATMAP_DURATION: f64 = 10.0;
+
+/// Size of the heatmap in the world units
+const HEATMAP_SIZE: f64 = 600.0;
+
+/// Divergence at which the meter is full, the copies are completely apart by then
+const FULL_DIVERGENCE: f64 = 500.0;
+
 const BG_COLOR: &str = "black";
 const BG_LINE_COLOR: &str = "#333040";
-const TARGET_COLOR: &str = "#183769";
+pub(crate) const TARGET_COLOR: &str = "#183769";
 const BONUS_COLOR: &str = "#ffdf00";
-const DANGER_COLOR: &str = "#730c05";
-
-fn draw_background(context: &Context<ChaosTheory>, spacing: f64) {
+pub(crate) const DANGER_COLOR: &str = "#730c05";
+const OBSTACLE_COLOR: &str = "#2b2838";
+const OBSTACLE_OUTLINE_COLOR: &str = "#6d6a80";
+const GRAVITY_COLOR: &str = "#4a4760";
+const WELL_COLOR: &str = "#1e6b5c";
+const FORCE_COLOR: &str = "#3a5a7a";
+const KINETIC_COLOR: &str = "#34eb7d";
+const POTENTIAL_COLOR: &str = "#3480eb";
+
+pub(crate) fn draw_background(context: &Context<ChaosTheory>, spacing: f64) {
     let size = context.surface().size();
     let half_size = size / 2.0;
     let surface = context.surface().context();
@@ -368,95 +325,1118 @@ fn draw_background(context: &Context<ChaosTheory>, spacing: f64) {
     }
 }
 
-fn draw_trail(surface: &SurfaceContext, trail: &VecDeque<V2>) {
-    surface.set_line_width(1.0);
+/// How many steps the older parts of the trails fade out in
+const TRAIL_FADE_STEPS: usize = 16;
+
+/// Neighbouring segments of a trail with the same fade and colour, drawn as a single path
+struct TrailRun {
+    /// The first and the last point of the run, the run starts at the segment ending in the first one
+    start: usize,
+    end: usize,
+    opacity: f64,
+    /// Index in the palette, none for the current colour
+    color: Option<usize>,
+}
+
+/// The older parts of the trail fade out, the segments that have a colour
+/// of their own in the palette are drawn with it instead of the current one
+fn trail_runs(len: usize, color: impl Fn(usize) -> Option<usize>) -> Vec<TrailRun> {
+    let fade = |i: usize| i * TRAIL_FADE_STEPS / len;
+    let mut runs = Vec::new();
+    let mut start = 1;
+    while start < len {
+        let (start_fade, start_color) = (fade(start), color(start));
+        let mut end = start;
+        while end + 1 < len && fade(end + 1) == start_fade && color(end + 1) == start_color {
+            end += 1;
+        }
+        runs.push(TrailRun {
+            start,
+            end,
+            opacity: (start_fade + 1) as f64 / TRAIL_FADE_STEPS as f64,
+            color: start_color,
+        });
+        start = end + 1;
+    }
+    runs
+}
+
+fn draw_trail(surface: &SurfaceContext, len: usize, pos: impl Fn(usize) -> V2, palette: &[String], color: impl Fn(usize) -> Option<usize>) {
+    for run in trail_runs(len, color) {
+        if let Some(color) = run.color {
+            surface.stroke_color(&palette[color]);
+        }
+        surface.set_global_alpha(run.opacity);
+        surface.set_line_width(1.0 + run.opacity);
+        surface.begin_path();
+        let first = pos(run.start - 1);
+        surface.move_to(first.x, first.y);
+        for i in run.start..=run.end {
+            let pos = pos(i);
+            surface.line_to(pos.x, pos.y);
+        }
+        surface.stroke();
+    }
+    surface.set_global_alpha(1.0);
+}
+
+/// Maximum speed, in units per second, kinetic energy, in units squared per second squared
+/// for each unit of mass, and time, in seconds, on the trail colour scales
+const TRAIL_MAX_SPEED: f64 = 3000.0;
+const TRAIL_MAX_KINETIC: f64 = 2e6;
+const TRAIL_MAX_TIME: f64 = 20.0;
+
+/// Where the sample falls on the scale of the colour mode, from 0 to 1
+fn trail_value(mode: TrailColor, trail: &VecDeque<TrailPoint>, i: usize) -> Option<f64> {
+    let value = match mode {
+        TrailColor::Plain => return None,
+        TrailColor::Speed => {
+            let prev = trail[i.saturating_sub(1)].pos;
+            (trail[i].pos - prev).magnitude() / TIME_STEP / TRAIL_MAX_SPEED
+        }
+        TrailColor::KineticEnergy => trail[i].kinetic / TRAIL_MAX_KINETIC,
+        TrailColor::Angle => trail[i].angle / TAU + 0.5,
+        TrailColor::Time => trail[i].time / TRAIL_MAX_TIME,
+    };
+    Some(value.clamp(0.0, 1.0))
+}
+
+/// How many colours the trail colour scales are made of
+const TRAIL_PALETTE_SIZE: usize = 64;
+
+/// The colour scale of the mode, from 0 to 1, empty for the plain trails
+fn trail_palette(mode: TrailColor) -> Vec<String> {
+    if mode == TrailColor::Plain {
+        return Vec::new();
+    }
+    (0..TRAIL_PALETTE_SIZE)
+        .map(|i| {
+            let value = i as f64 / (TRAIL_PALETTE_SIZE - 1) as f64;
+            // the angle wraps around, so its colours do too
+            let hue = if mode == TrailColor::Angle { value * 360.0 } else { (1.0 - value) * 240.0 };
+            format!("hsl({:.0}, 90%, 60%)", hue)
+        })
+        .collect()
+}
+
+/// Index of the colour of the sample in the palette of the mode
+fn trail_palette_index(mode: TrailColor, trail: &VecDeque<TrailPoint>, i: usize) -> Option<usize> {
+    trail_value(mode, trail, i).map(|value| (value * (TRAIL_PALETTE_SIZE - 1) as f64).round() as usize)
+}
+
+fn draw_run_trail(surface: &SurfaceContext, trail: &VecDeque<TrailPoint>, mode: TrailColor, palette: &[String]) {
+    draw_trail(surface, trail.len(), |i| trail[i].pos, palette, |i| trail_palette_index(mode, trail, i));
+}
+
+fn draw_trail_legend(surface: &SurfaceContext, mode: TrailColor, palette: &[String], bottom_left: V2) {
+    let (from, to) = match mode {
+        TrailColor::Plain => return,
+        TrailColor::Speed => (String::from("0"), format!("{}/s", TRAIL_MAX_SPEED)),
+        TrailColor::KineticEnergy => (String::from("0"), format!("{:e} u²/s²", TRAIL_MAX_KINETIC)),
+        TrailColor::Angle => (String::from("-π"), String::from("π")),
+        TrailColor::Time => (String::from("0s"), format!("{}s", TRAIL_MAX_TIME)),
+    };
+    let width = 200.0;
+    let height = 10.0;
+    let step = width / palette.len() as f64;
+    for (i, color) in palette.iter().enumerate() {
+        surface.fill_color(color);
+        surface.fill_rect(bottom_left.x + i as f64 * step, bottom_left.y - height, step + 0.5, height);
+    }
+    surface.fill_color("white");
+    surface.set_font("1rem monospace");
+    surface.set_text_align("left");
+    surface.fill_text(&format!("trails by {}: {}", mode.name(), from), bottom_left.x, bottom_left.y - height - 8.0).unwrap();
+    surface.set_text_align("right");
+    surface.fill_text(&to, bottom_left.x + width, bottom_left.y - height - 8.0).unwrap();
+    surface.set_text_align("center");
+}
+
+/// A part of the picture of the world, described once so that
+/// the canvas and the SVG export draw the exact same thing
+#[derive(Debug, Clone)]
+pub(crate) enum Shape<'a> {
+    Line { from: V2, to: V2, style: Style<'a> },
+    Polyline { points: Vec<V2>, style: Style<'a> },
+    /// Filled first and then stroked, if the style has both
+    Circle { pos: V2, radius: f64, style: Style<'a> },
+    /// Clockwise from the start angle to the end one
+    Arc { center: V2, radius: f64, start: f64, end: f64, style: Style<'a> },
+    /// Centered at the position, the canvas squeezes it into the given width if it is wider
+    Text { pos: V2, size: f64, text: String, color: &'a str, max_width: f64 },
+    /// The shapes cut to the area between two circles around the same center,
+    /// or to the whole outer one if the inner radius is zero
+    Clipped { center: V2, inner: f64, outer: f64, shapes: Vec<Shape<'a>> },
+}
+
+fn set_style(surface: &SurfaceContext, style: &Style) {
+    surface.set_global_alpha(style.opacity);
+    if let Some(color) = style.stroke {
+        surface.stroke_color(color);
+        surface.set_line_width(style.width);
+        surface.line_dash(style.dash.unwrap_or(&[]));
+    }
+    if let Some(color) = style.fill {
+        surface.fill_color(color);
+    }
+}
+
+/// The opacity and the dash are reset afterwards, the rest of the style is left as the last shape set it
+pub(crate) fn draw_shapes(surface: &SurfaceContext, shapes: &[Shape]) {
+    for shape in shapes {
+        match shape {
+            Shape::Line { from, to, style } => {
+                set_style(surface, style);
+                surface.line(*from, *to);
+            }
+            Shape::Polyline { points, style } => {
+                set_style(surface, style);
+                surface.begin_path();
+                for (i, pos) in points.iter().enumerate() {
+                    if i == 0 {
+                        surface.move_to(pos.x, pos.y);
+                    } else {
+                        surface.line_to(pos.x, pos.y);
+                    }
+                }
+                surface.stroke();
+            }
+            Shape::Circle { pos, radius, style } => {
+                set_style(surface, style);
+                if style.fill.is_some() {
+                    surface.fill_circle(*pos, *radius);
+                }
+                if style.stroke.is_some() {
+                    surface.circle(*pos, *radius);
+                }
+            }
+            Shape::Arc { center, radius, start, end, style } => {
+                set_style(surface, style);
+                surface.begin_path();
+                surface.arc(center.x, center.y, *radius, *start, *end).unwrap();
+                surface.stroke();
+            }
+            Shape::Text { pos, size, text, color, max_width } => {
+                surface.set_global_alpha(1.0);
+                surface.fill_color(color);
+                surface.set_font(&format!("{}px monospace", size));
+                surface.fill_text_with_max_width(text, pos.x, pos.y, *max_width).unwrap();
+            }
+            Shape::Clipped { center, inner, outer, shapes } => {
+                surface.save();
+                surface.begin_path();
+                if *inner > 0.0 {
+                    surface.arc(center.x, center.y, *inner, 0.0, TAU).unwrap();
+                    surface.arc(center.x, center.y, *outer, 0.0, TAU).unwrap();
+                    surface.close_path();
+                    surface.clip_evenodd();
+                } else {
+                    surface.arc(center.x, center.y, *outer, 0.0, TAU).unwrap();
+                    surface.clip();
+                }
+                draw_shapes(surface, shapes);
+                surface.restore();
+            }
+        }
+    }
+    surface.set_global_alpha(1.0);
+    surface.line_dash(&[]);
+}
 
-    let mut opacity = 0.0;
+/// Same as [draw_shapes], into the SVG
+fn svg_shapes(svg: &mut Svg, shapes: &[Shape]) {
+    for shape in shapes {
+        match shape {
+            Shape::Line { from, to, style } => svg.line(*from, *to, *style),
+            Shape::Polyline { points, style } => svg.polyline(points.iter().copied(), *style),
+            Shape::Circle { pos, radius, style } => svg.circle(*pos, *radius, *style),
+            Shape::Arc { center, radius, start, end, style } => svg.arc(*center, *radius, *start, *end, *style),
+            Shape::Text { pos, size, text, color, .. } => svg.text(*pos, *size, text, color),
+            Shape::Clipped { center, inner, outer, shapes } => {
+                if *inner > 0.0 {
+                    svg.clipped_ring(*center, *inner, *outer, |svg| svg_shapes(svg, shapes));
+                } else {
+                    svg.clipped(*center, *outer, |svg| svg_shapes(svg, shapes));
+                }
+            }
+        }
+    }
+}
 
-    if trail.len() > 1 {
-        let mut prev = trail[0];
-        for pos in trail.range(1..).copied() {
-            opacity += 1.0 / trail.len() as f64;
-            surface.set_global_alpha(opacity);
-            surface.set_line_width(1.0 + opacity);
-            surface.line(prev, pos);
-            prev = pos;
+/// The red zones and the closed areas around the targets, where the setup can not go
+fn forbidden_zone_shapes(level: &Level) -> Vec<Shape<'static>> {
+    let outline = Style::stroke(DANGER_COLOR, 4.0).with_dash(&[10.0, 10.0]);
+    let stripes = |center: V2, radius: f64| stripe_lines(center - v2![radius], center + v2![radius])
+        .into_iter()
+        .map(|(from, to)| Shape::Line { from, to, style: Style::stroke(DANGER_COLOR, 2.0).with_opacity(0.5) })
+        .collect();
+
+    let mut shapes = Vec::new();
+    for red_zone in &level.red_zones {
+        shapes.push(Shape::Circle { pos: red_zone.pos, radius: red_zone.radius, style: outline });
+        shapes.push(Shape::Clipped {
+            center: red_zone.pos,
+            inner: 0.0,
+            outer: red_zone.radius,
+            shapes: stripes(red_zone.pos, red_zone.radius),
+        });
+    }
+    for target in &level.targets {
+        let zone = &target.zone;
+        let closed = zone.radius + target.closed;
+        shapes.push(Shape::Circle { pos: zone.pos, radius: closed, style: outline });
+        shapes.push(Shape::Clipped {
+            center: zone.pos,
+            inner: zone.radius,
+            outer: closed,
+            shapes: stripes(zone.pos, closed),
+        });
+    }
+    shapes
+}
+
+pub(crate) fn draw_forbidden_zones(surface: &SurfaceContext, level: &Level) {
+    draw_shapes(surface, &forbidden_zone_shapes(level));
+}
+
+/// The shaft and the two sides of the head, none if it is too short to be seen
+fn arrow_lines(from: V2, to: V2) -> Option<[(V2, V2); 3]> {
+    let direction = to - from;
+    let length = direction.magnitude();
+    if length < 1.0 {
+        return None;
+    }
+    let back = -direction / length * length.min(8.0);
+    let side = v2![-back.y, back.x] / 2.0;
+    Some([(from, to), (to, to + back + side), (to, to + back - side)])
+}
+
+fn push_arrow<'a>(shapes: &mut Vec<Shape<'a>>, from: V2, to: V2, style: Style<'a>) {
+    for &(from, to) in arrow_lines(from, to).iter().flatten() {
+        shapes.push(Shape::Line { from, to, style });
+    }
+}
+
+/// Arrows along the gravity in the middle of each of the background cells of the view
+fn gravity_arrows(gravity: &Gravity, time: f64, half_size: V2) -> Vec<(V2, V2)> {
+    let mut arrows = Vec::new();
+    let spacing = 100.0;
+    let mut x = -(half_size.x / spacing).floor() * spacing;
+    while x <= half_size.x {
+        let mut y = -(half_size.y / spacing).floor() * spacing;
+        while y <= half_size.y {
+            let pos = v2![x + spacing / 2.0, y + spacing / 2.0];
+            let accel = gravity.at(pos, time);
+            let magnitude = accel.magnitude();
+            if magnitude > 0.0 {
+                // normal earth-ish gravity of 1000 gets the full length
+                let length = (magnitude / 1000.0).min(1.0) * spacing * 0.4;
+                let offset = accel / magnitude * length / 2.0;
+                arrows.push((pos - offset, pos + offset));
+            }
+            y += spacing;
+        }
+        x += spacing;
+    }
+    arrows
+}
+
+fn gravity_shapes(gravity: &Gravity, time: f64, half_size: V2) -> Vec<Shape<'static>> {
+    let mut shapes = Vec::new();
+    for (from, to) in gravity_arrows(gravity, time, half_size) {
+        push_arrow(&mut shapes, from, to, Style::stroke(GRAVITY_COLOR, 2.0));
+    }
+    shapes
+}
+
+pub(crate) fn draw_gravity(surface: &SurfaceContext, gravity: &Gravity, time: f64, half_size: V2) {
+    draw_shapes(surface, &gravity_shapes(gravity, time, half_size));
+}
+
+/// Streaks drifting along with the wind, not clipped to the zone
+fn wind_streaks(zone: &ForceZone, accel: V2, time: f64) -> Vec<(V2, V2)> {
+    let mut streaks = Vec::new();
+    let magnitude = accel.magnitude();
+    if magnitude > 0.0 {
+        let direction = accel / magnitude;
+        let side = v2![-direction.y, direction.x];
+        let diameter = zone.radius * 2.0;
+        let drift = time * magnitude.sqrt() * 4.0;
+        let mut i = 0;
+        let mut across = -zone.radius;
+        while across < zone.radius {
+            let along = (drift + i as f64 * 73.0) % diameter - zone.radius;
+            let from = zone.pos + side * across + direction * along;
+            streaks.push((from, from + direction * 30.0));
+            across += 40.0;
+            i += 1;
         }
     }
+    streaks
+}
+
+/// Arrows along the turbulence on a grid over the zone, not clipped to it
+fn turbulence_arrows(zone: &ForceZone, time: f64) -> Vec<(V2, V2)> {
+    let mut arrows = Vec::new();
+    let spacing = 50.0;
+    let mut x = -zone.radius;
+    while x <= zone.radius {
+        let mut y = -zone.radius;
+        while y <= zone.radius {
+            let pos = zone.pos + v2![x, y];
+            let accel = zone.accel(pos, V2::zeros(), time);
+            let length = (accel.magnitude() / 1000.0).min(1.0) * spacing * 0.4;
+            if length > 0.0 {
+                let offset = accel.normalize() * length / 2.0;
+                arrows.push((pos - offset, pos + offset));
+            }
+            y += spacing;
+        }
+        x += spacing;
+    }
+    arrows
+}
+
+/// Length of each of the spinning arcs of a vortex, in radians
+const VORTEX_ARC: f64 = 1.0;
+
+/// Radius and start angle of each of the spinning arcs of a vortex
+fn vortex_arcs(zone: &ForceZone, strength: f64, time: f64) -> Vec<(f64, f64)> {
+    let mut arcs = Vec::new();
+    let spin = time * strength.signum();
+    let mut radius = zone.radius / 4.0;
+    while radius < zone.radius {
+        for i in 0..3 {
+            arcs.push((radius, spin * zone.radius / radius + i as f64 * TAU / 3.0));
+        }
+        radius += zone.radius / 4.0;
+    }
+    arcs
+}
+
+/// Radius of the ring pulsing out of a damping zone
+fn damping_ring(zone: &ForceZone, time: f64) -> f64 {
+    zone.radius * (time * 0.5).fract()
+}
+
+/// The outline of the zone and what it does inside of it
+pub(crate) fn force_zone_shapes(zone: &ForceZone, time: f64) -> Vec<Shape<'static>> {
+    let style = Style::stroke(FORCE_COLOR, 2.0);
+    let mut inside = Vec::new();
+    match zone.force {
+        Force::Wind { accel } => {
+            for (from, to) in wind_streaks(zone, accel, time) {
+                inside.push(Shape::Line { from, to, style });
+            }
+        }
+        Force::Turbulence { .. } => {
+            for (from, to) in turbulence_arrows(zone, time) {
+                push_arrow(&mut inside, from, to, style);
+            }
+        }
+        Force::Vortex { strength } => {
+            for (radius, start) in vortex_arcs(zone, strength, time) {
+                inside.push(Shape::Arc { center: zone.pos, radius, start, end: start + VORTEX_ARC, style });
+            }
+        }
+        Force::Damping { .. } => {
+            inside.push(Shape::Circle { pos: zone.pos, radius: zone.radius, style: Style::fill(FORCE_COLOR).with_opacity(0.2) });
+            inside.push(Shape::Circle { pos: zone.pos, radius: damping_ring(zone, time), style: style.with_opacity(0.5) });
+        }
+    }
+    vec![
+        Shape::Circle { pos: zone.pos, radius: zone.radius, style: style.with_dash(&[5.0, 5.0]) },
+        Shape::Clipped { center: zone.pos, inner: 0.0, outer: zone.radius, shapes: inside },
+    ]
+}
+
+/// The paths that the driven roots follow, shown during the setup
+pub(crate) fn drive_shapes(level: &Level) -> Vec<Shape<'static>> {
+    level.pendulums.iter()
+        .filter_map(|pendulum| {
+            let drive = pendulum.drive.as_ref()?;
+            let root = pendulum.init_state.root;
+            let duration = drive.preview_duration();
+            let points = (0..=256).map(|i| root + drive.offset(duration * i as f64 / 256.0)).collect();
+            Some(Shape::Polyline { points, style: Style::stroke("gray", 2.0).with_dash(&[2.0, 10.0]) })
+        })
+        .collect()
+}
+
+fn rail_shapes(level: &Level) -> Vec<Shape<'static>> {
+    let style = Style::stroke("gray", 2.0);
+    level.pendulums.iter()
+        .filter_map(|pendulum| match &pendulum.rail {
+            Some(Rail::Line { from, to }) => Some(Shape::Line { from: *from, to: *to, style }),
+            Some(Rail::Area { zone }) => Some(Shape::Circle { pos: zone.pos, radius: zone.radius, style }),
+            None => None,
+        })
+        .collect()
+}
+
+/// Two rings for each of the wells, attractors and repulsors in different colours
+fn well_shapes(gravity: &Gravity) -> Vec<Shape<'static>> {
+    let mut shapes = Vec::new();
+    for well in &gravity.wells {
+        let style = Style::stroke(if well.strength > 0.0 { WELL_COLOR } else { DANGER_COLOR }, 2.0);
+        shapes.push(Shape::Circle { pos: well.pos, radius: well.radius, style });
+        shapes.push(Shape::Circle { pos: well.pos, radius: well.radius / 2.0, style });
+    }
+    shapes
+}
+
+fn obstacle_shapes(level: &Level) -> Vec<Shape<'static>> {
+    let style = Style::stroke(OBSTACLE_OUTLINE_COLOR, 4.0).with_fill(OBSTACLE_COLOR);
+    level.obstacles.iter()
+        .map(|obstacle| Shape::Circle { pos: obstacle.pos, radius: obstacle.radius, style })
+        .collect()
+}
+
+/// The rails, the wells and the obstacles, which stay where they are for the whole run
+pub(crate) fn fixture_shapes(level: &Level) -> Vec<Shape<'static>> {
+    let mut shapes = rail_shapes(level);
+    shapes.extend(well_shapes(&level.gravity));
+    shapes.extend(obstacle_shapes(level));
+    shapes
+}
+
+/// The targets filled in when they are hit, and in the bonus colour with the count when hit again
+pub(crate) fn target_shapes<'a>(level: &'a Level, touched: &[usize]) -> Vec<Shape<'a>> {
+    let mut shapes = Vec::new();
+    for (i, target) in level.targets.iter().enumerate() {
+        let zone = &target.zone;
+        let color = target.pendulum
+            .and_then(|p| level.pendulums.get(p))
+            .map_or(TARGET_COLOR, |p| &*p.color);
+        let hits = touched.get(i).copied().unwrap_or(0);
+        if hits > 0 {
+            let fill = if hits > 1 { BONUS_COLOR } else { color };
+            shapes.push(Shape::Circle { pos: zone.pos, radius: zone.radius, style: Style::fill(fill).with_opacity(0.5) });
+        }
+        if hits > 2 {
+            let half_radius = zone.radius / 2.0;
+            shapes.push(Shape::Text {
+                pos: zone.pos + v2![half_radius],
+                size: 24.0,
+                text: hits.to_string(),
+                color,
+                max_width: half_radius,
+            });
+        }
+        shapes.push(Shape::Circle { pos: zone.pos, radius: zone.radius, style: Style::stroke(color, 4.0) });
+    }
+    shapes
+}
+
+/// The segments with the joints and the root, whatever flies off after a snap is drawn as debris
+pub(crate) fn rope_shapes<'a>(rope: &Rope, color: &'a str) -> Vec<Shape<'a>> {
+    let points = rope.points();
+    let mut shapes = Vec::new();
+    let mut segment_color = color;
+    for constraint in &rope.constraints {
+        if constraint.broken {
+            segment_color = DANGER_COLOR;
+            continue;
+        }
+        let pos_b = points[constraint.point_b].pos();
+        shapes.push(Shape::Line { from: points[constraint.point_a].pos(), to: pos_b, style: Style::stroke(segment_color, 4.0) });
+        shapes.push(Shape::Circle { pos: pos_b, radius: 7.0, style: Style::fill(segment_color) });
+    }
+    shapes.push(Shape::Circle { pos: rope.root, radius: 15.0, style: Style::fill(color) });
+    shapes
+}
+
+/// The ropes of the run, with the tails marked in the colours of their pendulums when there are several
+fn pendulum_shapes<'a>(level: &'a Level, ropes: &[Rope]) -> Vec<Shape<'a>> {
+    let mut shapes = Vec::new();
+    for (pendulum, rope) in level.pendulums.iter().zip(ropes) {
+        shapes.extend(rope_shapes(rope, "white"));
+        if level.pendulums.len() > 1 {
+            shapes.push(Shape::Circle { pos: rope.tail(), radius: 7.0, style: Style::fill(&pendulum.color) });
+        }
+    }
+    shapes
+}
+
+/// Just the attached segments, for the copies of the butterfly mode
+fn copy_shapes<'a>(ropes: &[Rope], color: &'a str) -> Vec<Shape<'a>> {
+    let mut shapes = Vec::new();
+    for rope in ropes {
+        let points = rope.points();
+        for constraint in rope.constraints.iter().filter(|c| !c.broken) {
+            let (from, to) = (points[constraint.point_a].pos(), points[constraint.point_b].pos());
+            shapes.push(Shape::Line { from, to, style: Style::stroke(color, 2.0) });
+        }
+    }
+    shapes
+}
+
+fn draw_energy_graph(surface: &SurfaceContext, history: &VecDeque<Energy>, reference: f64, bottom_left: V2) {
+    let width = 300.0;
+    let height = 120.0;
+    let top = bottom_left.y - height;
+
+    surface.set_global_alpha(0.7);
+    surface.fill_color(BG_COLOR);
+    surface.fill_rect(bottom_left.x, top, width, height);
     surface.set_global_alpha(1.0);
+    surface.stroke_color(BG_LINE_COLOR);
+    surface.set_line_width(1.0);
+    surface.stroke_rect(bottom_left.x, top, width, height);
+
+    if history.len() < 2 {
+        return;
+    }
+
+    // the potential energy is only defined up to a constant, so all lines are fitted into the panel
+    let (min, max) = history.iter().fold((reference, reference), |(min, max), e| {
+        let total = e.total();
+        (min.min(e.kinetic).min(e.potential).min(total), max.max(e.kinetic).max(e.potential).max(total))
+    });
+    let range = (max - min).max(1.0);
+    let x = |i: usize| bottom_left.x + i as f64 * width / (60 * 10) as f64;
+    let y = |v: f64| bottom_left.y - (v - min) / range * height;
+
+    surface.line_dash(&[4.0, 4.0]);
+    surface.stroke_color("gray");
+    surface.line(v2![bottom_left.x, y(reference)], v2![bottom_left.x + width, y(reference)]);
+    surface.line_dash(&[]);
+
+    let lines: [(&str, fn(&Energy) -> f64); 3] = [
+        (KINETIC_COLOR, |e| e.kinetic),
+        (POTENTIAL_COLOR, |e| e.potential),
+        ("white", Energy::total),
+    ];
+    for &(color, value) in &lines {
+        surface.stroke_color(color);
+        surface.begin_path();
+        surface.move_to(x(0), y(value(&history[0])));
+        for (i, energy) in history.iter().enumerate().skip(1) {
+            surface.line_to(x(i), y(value(energy)));
+        }
+        surface.stroke();
+    }
+
+    let peak_kinetic = history.iter().map(|e| e.kinetic).fold(1.0, f64::max);
+    let drift = (history[history.len() - 1].total() - reference) / peak_kinetic * 100.0;
+    surface.fill_color("white");
+    surface.set_font("1rem monospace");
+    surface.set_text_align("left");
+    surface.fill_text(&format!("energy drift: {:+.1}%", drift), bottom_left.x + 5.0, top - 8.0).unwrap();
+    surface.set_text_align("center");
+}
+
+/// θ against ω of the chosen segment on the left, and its Poincaré section on the right if it has one
+fn draw_phase_plot(surface: &SurfaceContext, phase: &PhasePlot, has_section: bool, bottom_left: V2) {
+    let size = 200.0;
+    let top = bottom_left.y - size;
+
+    let (rope, segment) = phase.segment;
+    surface.fill_color("white");
+    surface.set_font("1rem monospace");
+    surface.set_text_align("left");
+    let what = if has_section { "θ/ω and Poincaré section" } else { "θ/ω, a single segment has no Poincaré section" };
+    surface.fill_text(
+        &format!("rope {} segment {}: {} (N to change)", rope, segment, what),
+        bottom_left.x + 5.0, top - 8.0,
+    ).unwrap();
+    surface.set_text_align("center");
+
+    let plots = if has_section { 2 } else { 1 };
+    for (i, (points, color)) in [(&phase.history, KINETIC_COLOR), (&phase.section, BONUS_COLOR)].iter().enumerate().take(plots) {
+        let left = bottom_left.x + i as f64 * (size + 10.0);
+
+        surface.set_global_alpha(0.7);
+        surface.fill_color(BG_COLOR);
+        surface.fill_rect(left, top, size, size);
+        surface.set_global_alpha(1.0);
+        surface.stroke_color(BG_LINE_COLOR);
+        surface.set_line_width(1.0);
+        surface.stroke_rect(left, top, size, size);
+        surface.line(v2![left + size / 2.0, top], v2![left + size / 2.0, bottom_left.y]);
+        surface.line(v2![left, top + size / 2.0], v2![left + size, top + size / 2.0]);
+
+        // θ is always within [-π, π], ω is fitted into the panel
+        let max_velocity = points.iter().map(|p| p.y.abs()).fold(1.0, f64::max);
+        let to_panel = |p: V2| v2![
+            left + (p.x / TAU + 0.5) * size,
+            top + (0.5 - p.y / max_velocity / 2.0) * size
+        ];
+
+        surface.stroke_color(color);
+        surface.fill_color(color);
+        if i == 0 {
+            for (a, b) in points.iter().zip(points.iter().skip(1)) {
+                // no line across the whole panel when the segment goes over the top
+                if (a.x - b.x).abs() < TAU / 2.0 {
+                    surface.line(to_panel(*a), to_panel(*b));
+                }
+            }
+        } else {
+            for &p in points.iter() {
+                surface.fill_circle(to_panel(p), 1.5);
+            }
+        }
+    }
 }
 
-fn draw_stripes(surface: &SurfaceContext, from: V2, to: V2) {
-    surface.set_global_alpha(0.5);
-    surface.set_line_width(2.0);
+/// The faster something happened the redder the cell, if it did not happen at all it is black
+fn draw_heatmap(surface: &SurfaceContext, sensitivity: &Sensitivity) {
+    let cell_size = HEATMAP_SIZE / sensitivity.resolution as f64;
+    let corner = -v2![HEATMAP_SIZE / 2.0];
+
+    surface.stroke_color(BG_LINE_COLOR);
+    surface.set_line_width(1.0);
+    surface.stroke_rect(corner.x, corner.y, HEATMAP_SIZE, HEATMAP_SIZE);
+
+    for (pos, color) in heatmap_cells(sensitivity) {
+        surface.fill_color(&color);
+        // slightly bigger so that there are no gaps between the cells
+        surface.fill_rect(pos.x, pos.y, cell_size + 0.5, cell_size + 0.5);
+    }
 
+    surface.fill_color("white");
+    surface.set_font("24px monospace");
+    surface.fill_text(&heatmap_caption(sensitivity), 0.0, corner.y - 12.0).unwrap();
+}
+
+/// Top left corner and colour of each of the heatmap cells that are done
+fn heatmap_cells(sensitivity: &Sensitivity) -> impl Iterator<Item = (V2, Cow<'static, str>)> + '_ {
+    let cell_size = HEATMAP_SIZE / sensitivity.resolution as f64;
+    let corner = -v2![HEATMAP_SIZE / 2.0];
+    sensitivity.cells().iter().enumerate().filter_map(move |(i, cell)| {
+        let color = match cell {
+            Cell::Pending => return None,
+            Cell::Forbidden => Cow::Borrowed(DANGER_COLOR),
+            Cell::Done(None) => Cow::Borrowed(BG_COLOR),
+            Cell::Done(Some(time)) => {
+                let hue = (time / sensitivity.duration()).sqrt() * 260.0;
+                Cow::Owned(format!("hsl({:.0}, 90%, 55%)", hue))
+            }
+        };
+        let x = corner.x + (i % sensitivity.resolution) as f64 * cell_size;
+        let y = corner.y + (i / sensitivity.resolution) as f64 * cell_size;
+        Some((v2![x, y], color))
+    })
+}
+
+fn heatmap_caption(sensitivity: &Sensitivity) -> String {
+    let what = match sensitivity.metric {
+        Metric::FirstFlip => "time to the first flip",
+        Metric::Win => "time to win",
+    };
+    if sensitivity.is_done() {
+        format!("{} over both segment angles, click to pick one", what)
+    } else {
+        format!("{} over both segment angles... {:.0}%", what, sensitivity.progress() * 100.0)
+    }
+}
+
+fn butterfly_color(index: usize, count: usize) -> String {
+    format!("hsl({}, 90%, 60%)", index * 360 / count)
+}
+
+/// The divergence is shown on a log scale, from the initial epsilon up to the copies being completely apart
+fn draw_divergence_meter(surface: &SurfaceContext, divergence: f64, epsilon: f64, bottom_left: V2) {
+    let width = 300.0;
+    let height = 16.0;
+    let top = bottom_left.y - height;
+
+    let fraction = ((divergence / epsilon).ln() / (FULL_DIVERGENCE / epsilon).ln()).clamp(0.0, 1.0);
+
+    surface.set_global_alpha(0.7);
+    surface.fill_color(BG_COLOR);
+    surface.fill_rect(bottom_left.x, top, width, height);
+    surface.set_global_alpha(1.0);
+    surface.fill_color(if fraction < 1.0 { BONUS_COLOR } else { DANGER_COLOR });
+    surface.fill_rect(bottom_left.x, top, width * fraction, height);
+    surface.stroke_color(BG_LINE_COLOR);
+    surface.set_line_width(1.0);
+    surface.stroke_rect(bottom_left.x, top, width, height);
+
+    surface.fill_color("white");
+    surface.set_font("1rem monospace");
+    surface.set_text_align("left");
+    surface.fill_text(&format!("divergence: {:.3}", divergence), bottom_left.x + 5.0, top - 8.0).unwrap();
+    surface.set_text_align("center");
+}
+
+/// Diagonal lines over the rectangle between the two corners
+fn stripe_lines(from: V2, to: V2) -> Vec<(V2, V2)> {
     let width_steps = (to.x - from.x) as u32 / 20;
     let height_steps = (to.y - from.y) as u32 / 20;
     let corner_steps = width_steps.min(height_steps);
 
-    surface.begin_path();
+    let mut lines = Vec::new();
     for i in 0..corner_steps {
-        surface.move_to(from.x + i as f64 * 20.0, from.y);
-        surface.line_to(from.x, from.y + i as f64 * 20.0);
+        lines.push((v2![from.x + i as f64 * 20.0, from.y], v2![from.x, from.y + i as f64 * 20.0]));
     }
     if width_steps > height_steps {
         for i in 0..(width_steps - height_steps) {
-            surface.move_to(from.x + (i + height_steps) as f64 * 20.0, from.y);
-            surface.line_to(from.x + i as f64 * 20.0, to.y);
+            lines.push((v2![from.x + (i + height_steps) as f64 * 20.0, from.y], v2![from.x + i as f64 * 20.0, to.y]));
         }
     } else {
         for i in 0..(height_steps - width_steps) {
-            surface.move_to(to.x, from.y + i as f64 * 20.0);
-            surface.line_to(from.x, from.y + (i + width_steps) as f64 * 20.0);
+            lines.push((v2![to.x, from.y + i as f64 * 20.0], v2![from.x, from.y + (i + width_steps) as f64 * 20.0]));
         }
     }
     for i in 0..corner_steps {
-        surface.move_to(to.x - (i + 1) as f64 * 20.0, to.y);
-        surface.line_to(to.x, to.y - (i + 1) as f64 * 20.0);
+        lines.push((v2![to.x - (i + 1) as f64 * 20.0, to.y], v2![to.x, to.y - (i + 1) as f64 * 20.0]));
+    }
+    lines
+}
+
+/// Puts the density into an offscreen canvas, one pixel per cell,
+/// so that drawing it every frame is just drawing a single image
+fn render_density(density: &TrailDensity) -> Result<HtmlCanvasElement, JsValue> {
+    let document = web_sys::window().and_then(|w| w.document()).ok_or("no document")?;
+    let canvas = document.create_element("canvas")?.dyn_into::<HtmlCanvasElement>()?;
+    canvas.set_width(density.width as u32);
+    canvas.set_height(density.height as u32);
+    let context = canvas.get_context("2d")?
+        .ok_or("no 2d context")?
+        .dyn_into::<CanvasRenderingContext2d>()?;
+    let mut pixels = density.to_rgba();
+    let image = ImageData::new_with_u8_clamped_array_and_sh(Clamped(&mut pixels), density.width as u32, density.height as u32)?;
+    context.put_image_data(&image, 0.0, 0.0)?;
+    Ok(canvas)
+}
+
+/// Makes the browser save the given contents as a file
+pub(crate) fn download(name: &str, mime: &str, contents: &str) {
+    let result = (|| -> Result<(), JsValue> {
+        let mut options = BlobPropertyBag::new();
+        options.type_(mime);
+        let blob = Blob::new_with_str_sequence_and_options(&Array::of1(&JsValue::from_str(contents)), &options)?;
+        let url = Url::create_object_url_with_blob(&blob)?;
+        download_url(name, &url)?;
+        Url::revoke_object_url(&url)
+    })();
+    if let Err(e) = result {
+        log::warn!("could not download {}: {:?}", name, e);
+    }
+}
+
+fn download_url(name: &str, url: &str) -> Result<(), JsValue> {
+    let document = web_sys::window().and_then(|w| w.document()).ok_or("no document")?;
+    let link = document.create_element("a")?.dyn_into::<HtmlAnchorElement>()?;
+    link.set_href(url);
+    link.set_download(name);
+    link.click();
+    Ok(())
+}
+
+/// Whether the setup of the run is over the Lyapunov exponent limit of the level,
+/// none while that is still being measured
+fn too_chaotic(lyapunov: &Option<Lyapunov>, level: &Level) -> Option<bool> {
+    match lyapunov {
+        Some(lyapunov) if level.max_lyapunov.is_some() => {
+            lyapunov.is_done().then(|| !lyapunov::exponent_within_limit(level, lyapunov.exponent()))
+        }
+        _ => Some(false),
+    }
+}
+
+/// Saves whatever is drawn on the canvas of the surface so far
+fn download_canvas(surface: &SurfaceContext, name: &str) {
+    let result = (|| -> Result<(), JsValue> {
+        let canvas = surface.canvas().ok_or("no canvas")?;
+        download_url(name, &canvas.to_data_url()?)
+    })();
+    if let Err(e) = result {
+        log::warn!("could not download {}: {:?}", name, e);
+    }
+}
+
+/// Same as [draw_trail], with a polyline for each of its runs
+fn svg_trail(
+    svg: &mut Svg, len: usize, pos: impl Fn(usize) -> V2,
+    palette: &[String], color: impl Fn(usize) -> Option<usize>, default_color: &str,
+) {
+    for run in trail_runs(len, color) {
+        let color = run.color.map_or(default_color, |c| palette[c].as_str());
+        let style = Style::stroke(color, 1.0 + run.opacity).with_opacity(run.opacity);
+        svg.polyline((run.start - 1..=run.end).map(&pos), style);
     }
-    surface.stroke();
 }
 
 impl MainGame {
-    fn constrain(&self, pos: V2) -> V2 {
-        for red_zone in &self.level.red_zones {
-            if red_zone.contains(pos) {
-                return red_zone.project(pos);
+    /// The level and the ropes over the trails, in the order both the canvas and the SVG draw them
+    fn world_shapes(&self, half_view: V2) -> Vec<Shape<'_>> {
+        let mut shapes = Vec::new();
+        if matches!(self.sim_status, SimStatus::Setup) {
+            shapes.extend(forbidden_zone_shapes(&self.level));
+            shapes.extend(gravity_shapes(&self.level.gravity, self.anim_time, half_view));
+            shapes.extend(drive_shapes(&self.level));
+        }
+        shapes.extend(fixture_shapes(&self.level));
+        shapes.extend(target_shapes(&self.level, self.sim.touched_targets()));
+        shapes.extend(pendulum_shapes(&self.level, &self.sim.ropes));
+        shapes
+    }
+
+    /// The same things the canvas shows in the world, the view is its size in the world units
+    fn to_svg(&self, view: V2, trail_color: TrailColor) -> String {
+        let top_left = -view / 2.0;
+        let mut svg = Svg::new(top_left, view);
+
+        svg.rect(top_left, view, Style::fill(BG_COLOR));
+        let spacing = 100.0;
+        let mut x = (top_left.x / spacing).ceil() * spacing;
+        while x < -top_left.x {
+            svg.line(v2![x, top_left.y], v2![x, -top_left.y], Style::stroke(BG_LINE_COLOR, 1.0));
+            x += spacing;
+        }
+        let mut y = (top_left.y / spacing).ceil() * spacing;
+        while y < -top_left.y {
+            svg.line(v2![top_left.x, y], v2![-top_left.x, y], Style::stroke(BG_LINE_COLOR, 1.0));
+            y += spacing;
+        }
+
+        for zone in &self.level.force_zones {
+            svg_shapes(&mut svg, &force_zone_shapes(zone, self.anim_time));
+        }
+
+        let palette = trail_palette(trail_color);
+        for (pendulum, trail) in self.level.pendulums.iter().zip(&self.trails) {
+            let color = |i| trail_palette_index(trail_color, trail, i);
+            svg_trail(&mut svg, trail.len(), |i| trail[i].pos, &palette, color, &pendulum.color);
+        }
+        if self.density_shown {
+            if !self.trail_density.is_empty() {
+                let density = &self.trail_density;
+                let rendered = match &self.density_canvas {
+                    Some(canvas) => canvas.to_data_url(),
+                    None => render_density(density).and_then(|canvas| canvas.to_data_url()),
+                };
+                match rendered {
+                    Ok(url) => {
+                        let size = v2![density.width as f64, density.height as f64] * density.cell_size;
+                        svg.image(density.origin, size, &url);
+                    }
+                    Err(e) => log::warn!("could not render the trail density: {:?}", e),
+                }
+            }
+        } else {
+            for trail in &self.prev_trails {
+                let color = |i| trail_palette_index(trail_color, trail, i);
+                svg_trail(&mut svg, trail.len(), |i| trail[i].pos, &palette, color, "gray");
+            }
+        }
+        if let Some(butterfly) = &self.butterfly {
+            let count = butterfly.copies.len();
+            for (i, copy) in butterfly.copies.iter().enumerate().filter(|(_, c)| !c.fell_apart) {
+                let color = butterfly_color(i, count);
+                for trail in &copy.trails {
+                    svg_trail(&mut svg, trail.len(), |i| trail[i], &[], |_| None, &color);
+                }
+                svg_shapes(&mut svg, &copy_shapes(&copy.sim.ropes, &color));
             }
         }
-        for target in &self.level.targets {
-            let constraint = target.zone.extend(target.closed);
-            if constraint.contains(pos) {
-                return constraint.project(pos);
+
+        svg_shapes(&mut svg, &self.world_shapes(view / 2.0));
+
+        if let (Some(sensitivity), SimStatus::Setup) = (&self.sensitivity, &self.sim_status) {
+            let corner = -v2![HEATMAP_SIZE / 2.0];
+            let cell_size = HEATMAP_SIZE / sensitivity.resolution as f64;
+            svg.rect(corner, v2![HEATMAP_SIZE], Style::stroke(BG_LINE_COLOR, 1.0));
+            for (pos, color) in heatmap_cells(sensitivity) {
+                svg.rect(pos, v2![cell_size + 0.5], Style::fill(&color));
             }
+            svg.text(v2![0.0, corner.y - 12.0], 24.0, &heatmap_caption(sensitivity), "white");
         }
-        pos
+
+        svg.finish()
     }
 
     fn reset(&mut self, soft: bool) {
         self.win_status = WinStatus::NotYet;
-        for t in self.touched_targets.iter_mut() {
-            *t = 0;
-        }
+        self.accumulator = 0.0;
+        self.control = Control::default();
+        self.energy_history.clear();
         match std::mem::replace(&mut self.sim_status, SimStatus::Setup) {
             SimStatus::Running { setup } | SimStatus::Paused { setup } if soft => {
-                self.rope = setup.clone();
-                self.rope.jiggle();
-                self.prev_trails.push_back(std::mem::take(&mut self.trail));
-                if self.prev_trails.len() > 127 {
+                self.start(setup.clone());
+                for trail in &mut self.trails {
+                    self.trail_density.add_trail(trail.iter().map(|p| p.pos));
+                    self.prev_trails.push_back(std::mem::take(trail));
+                }
+                self.density_canvas = None;
+                while self.prev_trails.len() > 127 {
                     self.prev_trails.pop_front();
                 }
                 self.sim_status = SimStatus::Running { setup }
             }
             _ => {
-                self.rope = self.level.init_state.clone();
-                self.trail.clear();
+                self.sim = Simulation::new(&self.level, self.level.init_state());
+                self.history.clear();
+                self.rewound = 0.0;
+                self.estimator = None;
+                self.lyapunov = None;
+                self.pending_win = None;
+                self.butterfly = None;
+                self.replay = None;
+                self.sensitivity = None;
+                self.trails.iter_mut().for_each(VecDeque::clear);
+                self.trajectory.clear();
+                self.phase.clear();
                 self.prev_trails.clear();
+                self.trail_density.clear();
+                self.density_canvas = None;
+            }
+        }
+    }
+
+    /// Starts a new run from the given setup, slightly jiggled
+    fn start(&mut self, setup: Vec<Rope>) {
+        let mut replay = Replay::new(setup, self.rng.next_u64(), 0.0);
+        let ropes = replay.jiggled_setup();
+        self.butterfly = if self.butterfly_mode {
+            Some(Butterfly::new(&self.level, &ropes, self.butterfly_count, self.butterfly_epsilon, &mut self.rng))
+        } else {
+            None
+        };
+        self.sim = Simulation::new(&self.level, ropes);
+        replay.outcome = Some(Outcome::new(&self.sim));
+        self.replay = Some(replay);
+        self.win_status = WinStatus::NotYet;
+        self.pending_win = None;
+        self.history.clear();
+        self.rewound = 0.0;
+        self.trajectory.clear();
+        self.phase.restart();
+    }
+
+    /// Advances the simulation by one fixed time step, returns true if any target got hit
+    fn step(&mut self) -> bool {
+        self.history.record(&self.sim);
+        let samples = self.sim.ropes.iter().map(|rope| TrailPoint::of(rope, self.sim.time)).collect::<Vec<_>>();
+
+        let result = self.sim.step(&self.level, self.control);
+        let chaotic = too_chaotic(&self.lyapunov, &self.level);
+        if chaotic.is_none() && self.sim.all_targets_hit() && result != StepResult::FellApart {
+            self.pending_win = Some(result);
+        }
+        // followed until the run fails, same as when the replay is played back
+        if let Some(Replay { controls, outcome: Some(outcome), .. }) = &mut self.replay {
+            if !outcome.failed {
+                controls.push(self.control);
+                if self.pending_win.is_none() {
+                    outcome.record(&self.sim, result, || chaotic == Some(false));
+                }
             }
         }
+
+        if result == StepResult::FellApart {
+            log::warn!("the simulation blew up at {}s", self.sim.time);
+            // back to the setup of the run, the message stays until the next one starts
+            if let SimStatus::Running { setup } | SimStatus::Paused { setup } = std::mem::replace(&mut self.sim_status, SimStatus::Setup) {
+                self.sim = Simulation::new(&self.level, setup);
+            }
+            self.control = Control::default();
+            self.butterfly = None;
+            self.replay = None;
+            self.history.clear();
+            self.win_status = WinStatus::Failed { reason: FailReason::FellApart };
+            return false;
+        }
+
+        self.trajectory.record(&self.sim, &self.level);
+        self.phase.record(&self.sim);
+
+        if let Some(butterfly) = &mut self.butterfly {
+            butterfly.step(&self.level, self.control);
+        }
+
+        for (trail, sample) in self.trails.iter_mut().zip(samples) {
+            trail.push_back(sample);
+            if trail.len() > 60 * 10 {
+                trail.pop_front();
+            }
+        }
+
+        self.energy_history.push_back(self.sim.energy(&self.level));
+        if self.energy_history.len() > 60 * 10 {
+            self.energy_history.pop_front();
+        }
+
+        result == StepResult::Hit
+    }
+
+    /// Loads the setup of the heatmap cell under the cursor, or closes the heatmap if there is none
+    fn pick_heatmap_cell(&mut self, pos: V2) {
+        let sensitivity = match self.sensitivity.take() {
+            Some(sensitivity) => sensitivity,
+            None => return,
+        };
+        let cell = (pos + v2![HEATMAP_SIZE / 2.0]) / HEATMAP_SIZE * HEATMAP_RESOLUTION as f64;
+        if cell.x < 0.0 || cell.y < 0.0 {
+            return;
+        }
+        let (x, y) = (cell.x as usize, cell.y as usize);
+        if x >= HEATMAP_RESOLUTION || y >= HEATMAP_RESOLUTION {
+            return;
+        }
+        match sensitivity.setup(&self.level, x, y) {
+            Some(setup) => {
+                self.sim = Simulation::new(&self.level, setup);
+                self.estimator = None;
+                self.lyapunov = None;
+            }
+            // clicking on a forbidden cell does nothing
+            None => self.sensitivity = Some(sensitivity),
+        }
+    }
+
+    /// Takes back the given number of the latest steps of the run, pausing it
+    fn rewind(&mut self, steps: usize) {
+        match self.win_status {
+            WinStatus::NotYet => {}
+            // taking back the snap is what rewinding is for
+            WinStatus::Failed { reason: FailReason::Snapped } => {}
+            // the win stands and a chaotic setup stays chaotic however far back the run goes
+            WinStatus::Won { .. } | WinStatus::Failed { .. } => return,
+        }
+        let (sim, steps) = match self.history.rewind(steps) {
+            Some(rewound) => rewound,
+            None => return,
+        };
+        self.sim = sim;
+        self.pending_win = None;
+        self.rewound += steps as f64 * TIME_STEP;
+        if let Some(replay) = &mut self.replay {
+            replay.controls.truncate((self.sim.time / TIME_STEP).round() as usize);
+            if let Some(outcome) = &mut replay.outcome {
+                outcome.rewind(&self.sim, &self.level);
+            }
+        }
+        // the snap is taken back along with the steps
+        self.win_status = WinStatus::NotYet;
+        // the copies can not be taken back, so they are just gone
+        self.butterfly = None;
+        for trail in &mut self.trails {
+            trail.truncate(trail.len().saturating_sub(steps));
+        }
+        self.energy_history.truncate(self.energy_history.len().saturating_sub(steps));
+        self.trajectory.rewind(&self.sim);
+        self.phase.restart();
+        self.pause();
+    }
+
+    /// Saves the run so far with how it went, so that it can be verified with the CLI
+    fn download_replay(&self) {
+        let mut replay = match &self.replay {
+            Some(replay) => replay.clone(),
+            None => return,
+        };
+        // the outcome is recorded as the run went, so verifying it natively checks that both of the platforms agree
+        replay.duration = self.sim.time;
+        replay.rewound = self.rewound;
+        download("replay.json", "application/json", &serde_json::to_string(&replay).expect("replays are always serializable"));
     }
 
     fn pause(&mut self) {
+        self.control = Control::default();
         self.sim_status = match std::mem::replace(&mut self.sim_status, SimStatus::Setup) {
             SimStatus::Setup => SimStatus::Setup,
             SimStatus::Running { setup } | SimStatus::Paused { setup } => SimStatus::Paused { setup },
@@ -489,6 +1469,13 @@ impl GameState<ChaosTheory> for MainGame {
         self.sound_button.text.pos /= 0.666;
         self.music_button.text.pos /= 0.666;
         self.tutorial_button.text.pos /= 0.666;
+        self.trail_color_button.text.pos /= 0.666;
+        self.csv_button.text.pos /= 0.666;
+        self.json_button.text.pos /= 0.666;
+        self.replay_button.text.pos /= 0.666;
+        self.svg_button.text.pos /= 0.666;
+        self.png_button.text.pos /= 0.666;
+        self.edit_button.text.pos /= 0.666;
         if self.next_level_button.on_event(&event, context) || self.skip_button.on_event(&event, context) {
             self.next_level = self.level.next_level.map(|f| f());
             return StateTransition::Pop;
@@ -522,6 +1509,31 @@ impl GameState<ChaosTheory> for MainGame {
             });
             self.next_level = Some(Level::tutorial_level());
             return StateTransition::Pop;
+        } else if self.trail_color_button.on_event(&event, context) {
+            let data = context.storage().clone();
+            context.set_storage(StoredData {
+                trail_color: data.trail_color.next(),
+                ..data
+            });
+            return StateTransition::None;
+        } else if self.csv_button.on_event(&event, context) {
+            download("trajectory.csv", "text/csv", &self.trajectory.to_csv());
+            return StateTransition::None;
+        } else if self.json_button.on_event(&event, context) {
+            download("trajectory.json", "application/json", &self.trajectory.to_json());
+            return StateTransition::None;
+        } else if self.replay_button.on_event(&event, context) {
+            self.download_replay();
+            return StateTransition::None;
+        } else if self.svg_button.on_event(&event, context) {
+            let view = context.surface().size() / 0.666;
+            download("chaos-theory.svg", "image/svg+xml", &self.to_svg(view, context.storage().trail_color));
+            return StateTransition::None;
+        } else if self.png_button.on_event(&event, context) {
+            self.png_requested = true;
+            return StateTransition::None;
+        } else if self.edit_button.on_event(&event, context) {
+            return StateTransition::push(Editor::new(self.level.clone()));
         }
         fn in_menu_button(pos: V2, size: V2) -> bool {
             let right = size.x / 2.0;
@@ -533,14 +1545,25 @@ impl GameState<ChaosTheory> for MainGame {
             pos.x * 0.666 > x1 && pos.x * 0.666 < x2 && pos.y * 0.666 > y1 && pos.y * 0.666 < y2
         }
         match event {
+            MouseDown { pos, .. } if matches!(self.sim_status, SimStatus::Setup) && self.sensitivity.is_some() => {
+                self.pick_heatmap_cell(pos);
+            }
             MouseDown { pos, .. } if matches!(self.sim_status, SimStatus::Setup) => {
-                if (self.rope.tail() - pos).magnitude() < 15.0 {
-                    self.creating = Some(pos)
+                self.creating = self.sim.ropes.iter()
+                    .position(|rope| (rope.tail() - pos).magnitude() < 15.0)
+                    .map(|i| (i, pos));
+            }
+            MouseDown { pos, .. } if matches!(self.sim_status, SimStatus::Running { .. }) => {
+                // the menu button still has to be clickable on the levels with rails
+                if self.level.has_rails() && !in_menu_button(pos, context.surface().size()) {
+                    self.control.steering = Some(pos);
                 }
             }
             MouseMove { pos, .. } => {
-                if self.creating.is_some() {
-                    self.creating = Some(self.constrain(pos))
+                if let Some((i, _)) = self.creating {
+                    self.creating = Some((i, self.level.constrain(pos)))
+                } else if self.control.steering.is_some() {
+                    self.control.steering = Some(pos)
                 } else if in_menu_button(pos, context.surface().size()) {
                     if !self.menu_hovered {
                         self.menu_hovered = true;
@@ -551,8 +1574,16 @@ impl GameState<ChaosTheory> for MainGame {
                 }
             }
             MouseUp { pos, button: MouseButton::Left } => {
-                if self.creating.is_some() {
-                    self.rope.add(self.constrain(pos));
+                if self.control.steering.is_some() {
+                    self.control.steering = None;
+                } else if let Some((i, _)) = self.creating {
+                    // too short segments are just dropped, same as a click without a drag
+                    let pos = self.level.constrain(pos);
+                    if self.sim.ropes[i].add(pos) {
+                        self.estimator = None;
+                        self.lyapunov = None;
+                        self.sensitivity = None;
+                    }
                     self.creating = None;
                 } else if in_menu_button(pos, context.surface().size()) {
                     self.menu_shown = !self.menu_shown;
@@ -561,6 +1592,13 @@ impl GameState<ChaosTheory> for MainGame {
                         self.sound_button.set_text("");
                         self.skip_button.set_text("");
                         self.tutorial_button.set_text("");
+                        self.trail_color_button.set_text("");
+                        self.csv_button.set_text("");
+                        self.json_button.set_text("");
+                        self.replay_button.set_text("");
+                        self.svg_button.set_text("");
+                        self.png_button.set_text("");
+                        self.edit_button.set_text("");
                     }
                     context.game.click.play_unique();
                 }
@@ -568,17 +1606,68 @@ impl GameState<ChaosTheory> for MainGame {
             KeyUp { code: 32, .. } => self.sim_status = match std::mem::replace(&mut self.sim_status, SimStatus::Setup) {
                 SimStatus::Setup => {
                     if self.creating.is_none() {
-                        let setup = self.rope.clone();
-                        self.rope.jiggle();
+                        self.accumulator = 0.0;
+                        self.energy_history.clear();
+                        let setup = self.sim.ropes.clone();
+                        self.sensitivity = None;
+                        // the measurement goes on during the run if it is not done yet
+                        let level = &self.level;
+                        self.lyapunov
+                            .get_or_insert_with(|| Lyapunov::new(level, setup.clone(), lyapunov::LYAPUNOV_DURATION));
+                        self.start(setup.clone());
                         SimStatus::Running { setup }
                     } else {
                         SimStatus::Setup
                     }
                 }
-                SimStatus::Running { setup } => SimStatus::Paused { setup },
+                SimStatus::Running { setup } => {
+                    self.control = Control::default();
+                    SimStatus::Paused { setup }
+                }
                 SimStatus::Paused { setup } => SimStatus::Running { setup }
             },
-            KeyUp { code: 67, .. } => self.prev_trails.clear(),
+            KeyDown { code: 37, .. } => self.control.arrows.x = -1.0,
+            KeyDown { code: 39, .. } => self.control.arrows.x = 1.0,
+            KeyDown { code: 38, .. } => self.control.arrows.y = -1.0,
+            KeyDown { code: 40, .. } => self.control.arrows.y = 1.0,
+            KeyUp { code: 37, .. } | KeyUp { code: 39, .. } => self.control.arrows.x = 0.0,
+            KeyUp { code: 38, .. } | KeyUp { code: 40, .. } => self.control.arrows.y = 0.0,
+            KeyUp { code: 67, .. } => {
+                self.prev_trails.clear();
+                self.trail_density.clear();
+                self.density_canvas = None;
+                self.phase.section.clear();
+            }
+            KeyUp { code: 27, .. } if self.playtest => return StateTransition::Pop,
+            KeyUp { code: 71, .. } => self.density_shown = !self.density_shown,
+            KeyUp { code: 80, .. } => self.phase_shown = !self.phase_shown,
+            KeyDown { code: 87, .. } if !matches!(self.sim_status, SimStatus::Setup) => self.rewinding = true,
+            KeyUp { code: 87, .. } => self.rewinding = false,
+            KeyUp { code: 65, .. } => self.speed = self.speed.saturating_sub(1),
+            KeyUp { code: 68, .. } => self.speed = (self.speed + 1).min(SPEEDS.len() - 1),
+            KeyUp { code: 83, .. } if matches!(self.sim_status, SimStatus::Paused { .. }) && self.pending_win.is_none() => {
+                if self.step() {
+                    context.game.target_hit.play();
+                }
+            }
+            KeyUp { code: 72, .. } if matches!(self.sim_status, SimStatus::Setup) => {
+                let metric = match &self.sensitivity {
+                    None => Some(Metric::FirstFlip),
+                    Some(sensitivity) if sensitivity.metric == Metric::FirstFlip => Some(Metric::Win),
+                    Some(_) => None,
+                };
+                self.sensitivity = metric.and_then(|metric| {
+                    let pendulum = self.sim.ropes.iter().position(|rope| rope.constraints.len() == 2)?;
+                    Sensitivity::new(self.sim.ropes.clone(), pendulum, HEATMAP_RESOLUTION, metric, HEATMAP_DURATION)
+                });
+            }
+            KeyUp { code: 78, .. } => self.phase.next_segment(&self.sim),
+            KeyUp { code: 69, .. } => self.energy_shown = !self.energy_shown,
+            KeyUp { code: 66, .. } => self.butterfly_mode = !self.butterfly_mode,
+            KeyUp { code: 219, .. } => self.butterfly_epsilon = (self.butterfly_epsilon / 10.0).max(1e-9),
+            KeyUp { code: 221, .. } => self.butterfly_epsilon = (self.butterfly_epsilon * 10.0).min(10.0),
+            KeyUp { code: 188, .. } => self.butterfly_count = (self.butterfly_count - 1).max(1),
+            KeyUp { code: 190, .. } => self.butterfly_count = (self.butterfly_count + 1).min(MAX_BUTTERFLY_COUNT),
             KeyUp {
                 code: 82,
                 meta: KeyMeta { shift, .. },
@@ -602,7 +1691,15 @@ impl GameState<ChaosTheory> for MainGame {
 
         context.game.background.play_unique();
 
-        let tail = self.rope.tail();
+        self.anim_time += context.delta_time();
+
+        for zone in &self.level.force_zones {
+            draw_shapes(&surface, &force_zone_shapes(zone, self.anim_time));
+        }
+
+        if self.rewinding && !matches!(self.sim_status, SimStatus::Setup) {
+            self.rewind(REWIND_SPEED);
+        }
 
         if matches!(self.sim_status, SimStatus::Running { .. }) {
             let mut delta_time = context.delta_time();
@@ -612,156 +1709,218 @@ impl GameState<ChaosTheory> for MainGame {
                 delta_time = 0.0;
             }
 
-            self.rope.simulate(self.level.gravity, delta_time, 15);
-
-            self.trail.push_back(tail);
-            if self.trail.len() > 60 * 10 {
-                self.trail.pop_front();
+            // the held run does not catch up once the win is decided
+            if self.pending_win.is_none() {
+                self.accumulator += delta_time * SPEEDS[self.speed];
+            }
+            while self.accumulator >= TIME_STEP && matches!(self.sim_status, SimStatus::Running { .. }) && self.pending_win.is_none() {
+                self.accumulator -= TIME_STEP;
+                if self.step() {
+                    context.game.target_hit.play();
+                }
             }
         }
 
-        surface.stroke_color("#7734eb");
-        draw_trail(&surface, &self.trail);
-
-        surface.stroke_color("gray");
-        for trail in &self.prev_trails {
-            draw_trail(&surface, trail);
+        let trail_color = context.storage().trail_color;
+        let palette = trail_palette(trail_color);
+        for (pendulum, trail) in self.level.pendulums.iter().zip(&self.trails) {
+            surface.stroke_color(&pendulum.color);
+            draw_run_trail(&surface, trail, trail_color, &palette);
         }
 
-        surface.fill_color("white");
-        surface.set_line_width(4.0);
-
-        if matches!(self.sim_status, SimStatus::Setup) {
-            surface.line_dash(&[10.0, 10.0]);
-            surface.stroke_color(DANGER_COLOR);
+        if self.density_shown {
+            if self.density_canvas.is_none() && !self.trail_density.is_empty() {
+                self.density_canvas = render_density(&self.trail_density)
+                    .map_err(|e| log::warn!("could not render the trail density: {:?}", e))
+                    .ok();
+            }
+            if let Some(canvas) = &self.density_canvas {
+                let density = &self.trail_density;
+                let size = v2![density.width as f64, density.height as f64] * density.cell_size;
+                surface.draw_image_with_html_canvas_element_and_dw_and_dh(canvas, density.origin.x, density.origin.y, size.x, size.y).unwrap();
+            }
+        } else {
+            surface.stroke_color("gray");
+            for trail in &self.prev_trails {
+                draw_run_trail(&surface, trail, trail_color, &palette);
+            }
+        }
 
-            for red_zone in &self.level.red_zones {
-                surface.circle(red_zone.pos, red_zone.radius);
+        if let Some(butterfly) = &self.butterfly {
+            let count = butterfly.copies.len();
+            for (i, copy) in butterfly.copies.iter().enumerate().filter(|(_, c)| !c.fell_apart) {
+                let color = butterfly_color(i, count);
+                surface.stroke_color(&color);
+                for trail in &copy.trails {
+                    draw_trail(&surface, trail.len(), |i| trail[i], &[], |_| None);
+                }
+                draw_shapes(&surface, &copy_shapes(&copy.sim.ropes, &color));
+            }
+        }
 
-                surface.save();
-                surface.clip();
+        let chaotic = too_chaotic(&self.lyapunov, &self.level);
+        if let (Some(result), Some(chaotic)) = (self.pending_win, chaotic) {
+            self.pending_win = None;
+            if let Some(Replay { outcome: Some(outcome), .. }) = &mut self.replay {
+                outcome.record(&self.sim, result, || !chaotic);
+            }
+        }
 
-                surface.set_global_alpha(0.5);
-                surface.line_dash(&[]);
-                surface.set_line_width(2.0);
+        let no_score = self.level.rewind == Rewind::NoScore && self.rewound > 0.0;
+        // nothing is decided while the run is held for the measurement
+        let undecided = matches!(self.win_status, WinStatus::NotYet) && self.pending_win.is_none();
+        if undecided && self.sim.all_targets_hit() && chaotic == Some(true) {
+            self.win_status = WinStatus::Failed { reason: FailReason::TooChaotic };
+        } else if let WinStatus::Won { time, .. } = self.win_status {
+            self.win_status = WinStatus::Won {
+                bonuses: self.sim.bonuses(),
+                time,
+            };
+        } else if undecided && self.sim.all_targets_hit() && chaotic == Some(false) && !no_score {
+            self.win_status = WinStatus::Won {
+                bonuses: self.sim.bonuses(),
+                time: self.sim.time + self.rewound,
+            };
+        } else if undecided && self.sim.is_broken() {
+            self.win_status = WinStatus::Failed { reason: FailReason::Snapped };
+        }
 
-                draw_stripes(
-                    &surface,
-                    red_zone.pos - v2![red_zone.radius],
-                    red_zone.pos + v2![red_zone.radius],
-                );
+        draw_shapes(&surface, &self.world_shapes(size / 2.0 / scale_fix));
 
-                surface.restore();
+        if let (Some(sensitivity), SimStatus::Setup) = (&mut self.sensitivity, &self.sim_status) {
+            let start = js_sys::Date::now();
+            while !sensitivity.is_done() && js_sys::Date::now() - start < ESTIMATE_BUDGET {
+                sensitivity.advance(&self.level, 10);
             }
+            draw_heatmap(&surface, sensitivity);
+        }
 
-            // surface.stroke_color(TARGET_COLOR);
-            for target in &self.level.targets {
-                let zone = &target.zone;
-                let closed = zone.radius + target.closed;
-                surface.circle(zone.pos, closed);
-
-                surface.begin_path();
-                surface
-                    .arc(zone.pos.x, zone.pos.y, zone.radius, 0.0, TAU)
-                    .unwrap();
-                surface
-                    .arc(zone.pos.x, zone.pos.y, closed, 0.0, TAU)
-                    .unwrap();
-                surface.close_path();
-
-                surface.save();
-                surface.clip_evenodd();
+        // only the world, without the menu and the rest of the interface
+        if self.png_requested {
+            self.png_requested = false;
+            download_canvas(&surface, "chaos-theory.png");
+        }
 
-                surface.line_dash(&[]);
-                surface.set_line_width(2.0);
+        surface.scale(1.0 / scale_fix, 1.0 / scale_fix).unwrap();
 
-                draw_stripes(&surface, zone.pos - v2![closed], zone.pos + v2![closed]);
+        let right = size.x / 2.0;
+        let top = -size.y / 2.0;
 
-                surface.restore();
-                surface.set_global_alpha(1.0);
-            }
-            surface.line_dash(&[]);
+        if self.energy_shown {
+            draw_energy_graph(&surface, &self.energy_history, self.sim.reference_energy(), v2![-right + 20.0, -top - 20.0]);
         }
 
-        surface.stroke_color(TARGET_COLOR);
-        surface.fill_color(TARGET_COLOR);
-        surface.set_line_width(4.0);
+        draw_trail_legend(&surface, trail_color, &palette, v2![-right + 20.0, top + 60.0]);
 
-        let mut reset_touching_target = true;
-        for (i, target) in self.level.targets.iter().enumerate() {
-            let zone = &target.zone;
-            if zone.contains(tail) {
-                if self.touching_target.is_none() {
-                    self.touched_targets[i] += 1;
-                    context.game.target_hit.play();
-                }
-                self.touching_target = Some(i);
-                reset_touching_target = false;
-            }
-            let level = self.touched_targets[i];
-            if level > 0 {
-                surface.set_global_alpha(0.5);
-                if level > 1 {
-                    surface.fill_color(BONUS_COLOR);
-                }
-                surface.fill_circle(zone.pos, zone.radius);
-                surface.set_global_alpha(1.0);
-                if level > 1 {
-                    surface.fill_color(TARGET_COLOR);
-                }
-                if level > 2 {
-                    let half_radius = zone.radius / 2.0;
-                    surface.set_font("24px monospace");
-                    surface
-                        .fill_text_with_max_width(
-                            &level.to_string(),
-                            zone.pos.x + half_radius,
-                            zone.pos.y + half_radius,
-                            half_radius,
-                        )
-                        .unwrap();
-                }
-            }
-            surface.circle(zone.pos, zone.radius);
+        if !matches!(self.sim_status, SimStatus::Setup) || self.speed != NORMAL_SPEED {
+            let paused = if matches!(self.sim_status, SimStatus::Paused { .. }) { ", paused (s to step)" } else { "" };
+            surface.fill_color("white");
+            surface.set_font("1rem monospace");
+            surface.set_text_align("left");
+            surface.fill_text(
+                &format!("t = {:.2}s, speed ×{} (a and d to change){}", self.sim.time, SPEEDS[self.speed], paused),
+                -right + 20.0, top + 20.0,
+            ).unwrap();
+            surface.set_text_align("center");
         }
 
-        if matches!(self.win_status, WinStatus::Won { .. })
-            || matches!(self.win_status, WinStatus::NotYet)
-            && self.touched_targets.iter().all(|&i| i > 0)
-        {
-            let sum = self.touched_targets.iter().sum::<usize>();
-            self.win_status = WinStatus::Won {
-                bonuses: sum - self.touched_targets.len(),
-            };
+        if self.phase_shown {
+            draw_phase_plot(&surface, &self.phase, self.phase.has_section(&self.sim), v2![-right + 20.0, -top - 200.0]);
         }
 
-        if reset_touching_target {
-            self.touching_target = None;
+        if let Some(butterfly) = &self.butterfly {
+            draw_divergence_meter(&surface, butterfly.divergence(&self.sim), butterfly.epsilon, v2![right - 320.0, -top - 20.0]);
+        } else if self.butterfly_mode {
+            surface.fill_color("gray");
+            surface.set_font("1.2rem monospace");
+            let text = format!(
+                "butterfly mode: {} copies, ε = {:e} ([ ] and , . to change)",
+                self.butterfly_count, self.butterfly_epsilon,
+            );
+            surface.fill_text(&text, 0.0, -top - context.rem_to_px(3.4)).unwrap();
         }
 
-        surface.stroke_color("white");
-        surface.fill_color("white");
-
-        for constraint in &self.rope.constraints {
-            let pos_b = constraint.point_b.borrow().pos();
-            surface.line(constraint.point_a.borrow().pos(), pos_b);
-            surface.fill_circle(pos_b, 7.0);
+        if self.playtest {
+            surface.fill_color("gray");
+            surface.set_font("1.2rem monospace");
+            surface.fill_text("play-testing, esc to go back to the editor", 0.0, -top - context.rem_to_px(4.6)).unwrap();
         }
 
-        surface.fill_circle(self.rope.root, 15.0);
-
-        surface.scale(1.0 / scale_fix, 1.0 / scale_fix).unwrap();
+        if matches!(self.sim_status, SimStatus::Setup) {
+            // too chaotic setups never win, so there is nothing to estimate
+            let too_chaotic = self.lyapunov.as_ref()
+                .map_or(false, |l| l.is_done() && !lyapunov::exponent_within_limit(&self.level, l.exponent()));
+            if self.estimator.is_none() && !self.level.has_rails() {
+                let setup = self.sim.ropes.clone();
+                self.estimator = Some(Estimator::new(setup, ESTIMATE_RUNS, ESTIMATE_DURATION, self.rng.fork()));
+            }
+            if let Some(estimator) = &mut self.estimator {
+                let start = js_sys::Date::now();
+                while !estimator.is_done() && js_sys::Date::now() - start < ESTIMATE_BUDGET {
+                    estimator.advance(&self.level, 10);
+                }
+                let text = if too_chaotic {
+                    String::from("this setup never wins, it is too chaotic")
+                } else if estimator.is_done() {
+                    let estimate = estimator.estimate();
+                    let rate = format!("this setup wins ~{:.0}% of the time", estimate.win_rate() * 100.0);
+                    match estimate.win_time_quantile(0.5) {
+                        Some(time) => format!(
+                            "{}, usually in ~{:.1}s with ~{:.1} bonuses",
+                            rate, time, estimate.mean_bonuses(),
+                        ),
+                        None => rate,
+                    }
+                } else {
+                    format!("estimating the win chance... {:.0}%", estimator.progress() * 100.0)
+                };
+                surface.fill_color("gray");
+                surface.set_font("1.2rem monospace");
+                surface.fill_text(&text, 0.0, -top - context.rem_to_px(1.0)).unwrap();
+            } else if self.level.has_rails() {
+                // the estimate runs without anyone steering, so it would say nothing about the setup
+                surface.fill_color("gray");
+                surface.set_font("1.2rem monospace");
+                surface.fill_text("no win chance estimate, it depends on the steering", 0.0, -top - context.rem_to_px(1.0)).unwrap();
+            }
 
-        let right = size.x / 2.0;
-        let top = -size.y / 2.0;
+            let level = &self.level;
+            let ropes = &self.sim.ropes;
+            let lyapunov = self.lyapunov
+                .get_or_insert_with(|| Lyapunov::new(level, ropes.clone(), lyapunov::LYAPUNOV_DURATION));
+            // measured after the estimate is done, so that the frame budget is not spent twice
+            if self.estimator.as_ref().map_or(true, Estimator::is_done) {
+                let start = js_sys::Date::now();
+                while !lyapunov.is_done() && js_sys::Date::now() - start < ESTIMATE_BUDGET {
+                    lyapunov.advance(level, 10);
+                }
+            }
+            let limit = level.max_lyapunov.map(|max| format!(" (must be at most {:.2})", max)).unwrap_or_default();
+            let (text, color) = match lyapunov.exponent() {
+                Some(exponent) if lyapunov.is_done() => (
+                    format!("chaos score: λ ≈ {:.2}/s{}", exponent, limit),
+                    if level.max_lyapunov.map_or(false, |max| exponent > max) { DANGER_COLOR } else { "gray" },
+                ),
+                _ if lyapunov.is_done() => (format!("chaos score: none{}", limit), "gray"),
+                _ => (format!("measuring the chaos... {:.0}%", lyapunov.progress() * 100.0), "gray"),
+            };
+            surface.fill_color(color);
+            surface.set_font("1.2rem monospace");
+            surface.fill_text(&text, 0.0, -top - context.rem_to_px(2.2)).unwrap();
+        } else if let (Some(lyapunov), Some(_)) = (&mut self.lyapunov, self.level.max_lyapunov) {
+            let start = js_sys::Date::now();
+            while !lyapunov.is_done() && js_sys::Date::now() - start < ESTIMATE_BUDGET {
+                lyapunov.advance(&self.level, 10);
+            }
+        }
 
-        if let Some(title) = self.level.custom_text {
+        if let Some(title) = &self.level.custom_text {
             surface.fill_color("white");
             surface.set_font("1.5rem monospace");
             surface.fill_text(title, 0.0, top + context.rem_to_px(1.0)).unwrap();
         }
 
-        if let WinStatus::Won { bonuses } = self.win_status {
+        if let WinStatus::Won { bonuses, time } = self.win_status {
             surface.fill_color("white");
             surface.set_font("2.5rem monospace");
             let text =
@@ -772,6 +1931,16 @@ impl GameState<ChaosTheory> for MainGame {
                 };
             surface.fill_text(text.as_ref(), 0.0, top + context.rem_to_px(2.5)).unwrap();
 
+            // the time is the score besides the bonuses, the rewound time counts against it
+            surface.set_font("1.2rem monospace");
+            let text = if self.rewound > 0.0 {
+                format!("in {:.1}s, {:.1}s of it rewound", time, self.rewound)
+            } else {
+                format!("in {:.1}s", time)
+            };
+            surface.fill_text(&text, 0.0, top + context.rem_to_px(6.5)).unwrap();
+            surface.set_font("2.5rem monospace");
+
             if self.level.next_level.is_none() {
                 surface.fill_text("That's all there is for now 🤷", 0.0, top + context.rem_to_px(5.0)).unwrap();
                 self.next_level_button.set_text("");
@@ -779,6 +1948,19 @@ impl GameState<ChaosTheory> for MainGame {
                 self.next_level_button.set_text("next level");
             }
             self.next_level_button.on_update(context, v2![0.0, top + context.rem_to_px(2.5) + context.rem_to_px(1.6)]);
+        } else if let WinStatus::Failed { reason } = self.win_status {
+            surface.fill_color(DANGER_COLOR);
+            surface.set_font("2.5rem monospace");
+            surface.fill_text(&reason.to_string(), 0.0, top + context.rem_to_px(2.5)).unwrap();
+        } else if self.pending_win.is_some() {
+            let progress = self.lyapunov.as_ref().map_or(1.0, Lyapunov::progress);
+            surface.fill_color("gray");
+            surface.set_font("1.5rem monospace");
+            surface.fill_text(&format!("measuring the chaos before the win... {:.0}%", progress * 100.0), 0.0, top + context.rem_to_px(2.5)).unwrap();
+        } else if self.level.rewind == Rewind::NoScore && self.rewound > 0.0 {
+            surface.fill_color("gray");
+            surface.set_font("1.5rem monospace");
+            surface.fill_text("Rewound, this run can not be won", 0.0, top + context.rem_to_px(2.5)).unwrap();
         }
 
         surface.fill_color(if self.menu_hovered { HOVER_COLOR } else { BUTTON_COLOR });
@@ -800,8 +1982,9 @@ impl GameState<ChaosTheory> for MainGame {
             } else {
                 "Sounds: Off"
             });
-            self.skip_button.set_text("Skip level");
-            self.tutorial_button.set_text("Replay tutorial");
+            // both of them leave for the campaign, which does not belong on top of the editor
+            self.skip_button.set_text(if self.playtest { "" } else { "Skip level" });
+            self.tutorial_button.set_text(if self.playtest { "" } else { "Replay tutorial" });
 
             let (music_button_width, _) = self.music_button.text.compute_size(context);
             let (sound_button_width, _) = self.sound_button.text.compute_size(context);
@@ -814,6 +1997,48 @@ impl GameState<ChaosTheory> for MainGame {
             self.sound_button.on_update(context, v2![right - sound_button_width / 2.0, top + context.rem_to_px(1.3)]);
             self.skip_button.on_update(context, v2![right - skip_button_width / 2.0, top + context.rem_to_px(2.6)]);
             self.tutorial_button.on_update(context, v2![right - tutorial_button_width / 2.0, top + context.rem_to_px(3.9)]);
+
+            self.trail_color_button.set_text(match context.storage().trail_color {
+                TrailColor::Plain => "Trails: plain",
+                TrailColor::Speed => "Trails: speed",
+                TrailColor::KineticEnergy => "Trails: energy",
+                TrailColor::Angle => "Trails: angle",
+                TrailColor::Time => "Trails: time",
+            });
+            let (trail_color_button_width, _) = self.trail_color_button.text.compute_size(context);
+            self.trail_color_button.on_update(context, v2![right - trail_color_button_width / 2.0, top + context.rem_to_px(5.2)]);
+
+            // the run can only be downloaded while it is paused
+            if matches!(self.sim_status, SimStatus::Paused { .. }) && !self.trajectory.is_empty() {
+                self.csv_button.set_text("Download CSV");
+                self.json_button.set_text("Download JSON");
+            } else {
+                self.csv_button.set_text("");
+                self.json_button.set_text("");
+            }
+            if matches!(self.sim_status, SimStatus::Paused { .. }) && self.replay.is_some() {
+                self.replay_button.set_text("Download replay");
+            } else {
+                self.replay_button.set_text("");
+            }
+            let (csv_button_width, _) = self.csv_button.text.compute_size(context);
+            let (json_button_width, _) = self.json_button.text.compute_size(context);
+            let (replay_button_width, _) = self.replay_button.text.compute_size(context);
+            self.csv_button.on_update(context, v2![right - csv_button_width / 2.0, top + context.rem_to_px(6.5)]);
+            self.json_button.on_update(context, v2![right - json_button_width / 2.0, top + context.rem_to_px(7.8)]);
+            self.replay_button.on_update(context, v2![right - replay_button_width / 2.0, top + context.rem_to_px(9.1)]);
+
+            self.svg_button.set_text("Save SVG");
+            self.png_button.set_text("Save PNG");
+            let (svg_button_width, _) = self.svg_button.text.compute_size(context);
+            let (png_button_width, _) = self.png_button.text.compute_size(context);
+            self.svg_button.on_update(context, v2![right - svg_button_width / 2.0, top + context.rem_to_px(10.4)]);
+            self.png_button.on_update(context, v2![right - png_button_width / 2.0, top + context.rem_to_px(11.7)]);
+
+            // editing a level while play-testing it would just stack up the editors
+            self.edit_button.set_text(if self.playtest { "" } else { "Edit level" });
+            let (edit_button_width, _) = self.edit_button.text.compute_size(context);
+            self.edit_button.on_update(context, v2![right - edit_button_width / 2.0, top + context.rem_to_px(13.0)]);
         }
 
         if let Some(tutorial) = &mut self.tutorial {
@@ -823,8 +2048,8 @@ impl GameState<ChaosTheory> for MainGame {
         }
         surface.scale(scale_fix, scale_fix).unwrap();
 
-        if let Some(pos) = self.creating {
-            let tail = self.rope.tail();
+        if let Some((i, pos)) = self.creating {
+            let tail = self.sim.ropes[i].tail();
 
             surface.stroke_color("gray");
             surface.fill_color("white");
@@ -847,6 +2072,10 @@ impl GameState<ChaosTheory> for MainGame {
     }
 
     fn on_popped(self: Box<Self>, _context: &mut Context<ChaosTheory>) -> StateTransition<ChaosTheory> {
-        StateTransition::push(MainGame::new(self.next_level.unwrap_or(self.level), _context.game))
+        match self.next_level {
+            // the editor is right under the play-test, whatever level it was left for
+            _ if self.playtest => StateTransition::None,
+            next_level => StateTransition::push(MainGame::new(next_level.unwrap_or(self.level), _context.game)),
+        }
     }
 }
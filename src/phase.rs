@@ -0,0 +1,129 @@
+use std::{collections::VecDeque, f64::consts::FRAC_PI_2};
+
+use ld_game_engine::{v2, V2};
+
+use crate::sim::{Simulation, TIME_STEP};
+
+/// How many of the latest steps of the run the phase curve shows
+const HISTORY_LENGTH: usize = 60 * 10;
+
+/// How many of the latest section points are kept over all of the runs
+const SECTION_LENGTH: usize = 4096;
+
+/// Follows the angle θ and the angular velocity ω of a chosen segment.
+/// Whenever that segment swings through the vertical the other segment of
+/// the section (the next one on the same rope, wrapping around) is sampled into the
+/// Poincaré section, which is kept over the runs the same way the previous trails are.
+/// A rope of a single segment has no other one, so there is no section for it
+#[derive(Debug, Clone, Default)]
+pub struct PhasePlot {
+    /// Index of the rope and of the segment on it
+    pub segment: (usize, usize),
+    /// (θ, ω) of the chosen segment on each of the steps of the current run
+    pub history: VecDeque<V2>,
+    pub section: VecDeque<V2>,
+    prev_angle: Option<f64>,
+}
+
+impl PhasePlot {
+    /// Switches to the next segment of all the ropes, forgetting everything about the current one
+    pub fn next_segment(&mut self, sim: &Simulation) {
+        let (mut rope, mut segment) = self.segment;
+        segment += 1;
+        while sim.ropes.get(rope).map_or(false, |r| segment >= r.constraints.len()) {
+            rope += 1;
+            segment = 0;
+        }
+        if rope >= sim.ropes.len() {
+            rope = 0;
+        }
+        self.segment = (rope, segment);
+        self.clear();
+    }
+
+    /// Whether the rope of the chosen segment has another one to sample into the section
+    pub fn has_section(&self, sim: &Simulation) -> bool {
+        sim.ropes.get(self.segment.0).map_or(false, |rope| rope.constraints.len() > 1)
+    }
+
+    /// Starts over with a new run, the section is kept
+    pub fn restart(&mut self) {
+        self.history.clear();
+        self.prev_angle = None;
+    }
+
+    pub fn clear(&mut self) {
+        self.restart();
+        self.section.clear();
+    }
+
+    /// Should be called after each of the steps of the simulation
+    pub fn record(&mut self, sim: &Simulation) {
+        let (rope, segment) = self.segment;
+        let rope = match sim.ropes.get(rope) {
+            Some(rope) if segment < rope.constraints.len() => rope,
+            _ => return,
+        };
+        let (angle, velocity) = rope.segment_phase(segment, TIME_STEP);
+
+        self.history.push_back(v2![angle, velocity]);
+        if self.history.len() > HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+
+        // only going through the bottom counts, not the jump from π to -π at the top
+        let crossed = self.prev_angle.map_or(false, |prev| prev < 0.0 && angle >= 0.0 && angle - prev < FRAC_PI_2);
+        if crossed && rope.constraints.len() > 1 {
+            let other = (segment + 1) % rope.constraints.len();
+            let (other_angle, other_velocity) = rope.segment_phase(other, TIME_STEP);
+            self.section.push_back(v2![other_angle, other_velocity]);
+            if self.section.len() > SECTION_LENGTH {
+                self.section.pop_front();
+            }
+        }
+        self.prev_angle = Some(angle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        level::{Level, Pendulum},
+        rope::Rope,
+        sim::Control,
+    };
+
+    /// Phase plot of the first segment after a few seconds of swinging from the given points
+    fn plot(points: &[V2]) -> (PhasePlot, Simulation) {
+        let mut rope = Rope::new(v2![0.0, 0.0]);
+        for &point in points {
+            assert!(rope.add(point));
+        }
+        let mut level = Level::tutorial_level();
+        level.pendulums = vec![Pendulum::new(rope)];
+        let mut sim = Simulation::new(&level, level.init_state());
+        let mut phase = PhasePlot::default();
+        for _ in 0..60 * 5 {
+            sim.step(&level, Control::default());
+            phase.record(&sim);
+        }
+        (phase, sim)
+    }
+
+    #[test]
+    fn double_pendulum_has_a_section() {
+        let (phase, sim) = plot(&[v2![100.0, 0.0], v2![200.0, 0.0]]);
+        assert!(phase.has_section(&sim));
+        assert!(!phase.history.is_empty());
+        assert!(!phase.section.is_empty());
+    }
+
+    #[test]
+    fn single_segment_has_no_section() {
+        let (phase, sim) = plot(&[v2![100.0, 0.0]]);
+        assert!(!phase.has_section(&sim));
+        assert!(!phase.history.is_empty());
+        assert!(phase.section.is_empty());
+    }
+}
@@ -0,0 +1,333 @@
+use serde::*;
+
+use crate::{
+    level::{Level, Rewind},
+    lyapunov,
+    rng::Rng,
+    rope::Rope,
+    sim::{Control, Simulation, StepResult, TIME_STEP},
+};
+
+/// How a single run ended
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Outcome {
+    /// When all of the targets were hit, not set if they never were or if the run failed
+    pub won_at: Option<f64>,
+    /// The simulation blew up, the rope snapped before the win or the setup was too chaotic to win
+    pub failed: bool,
+    /// How many times each of the targets was hit
+    pub touched_targets: Vec<usize>,
+    /// When the run stopped, either at its full duration or when it failed
+    pub time: f64,
+    /// The run was rewound on a level with [Rewind::NoScore], so hitting all of the targets does not win it
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub scoreless: bool,
+}
+
+impl Outcome {
+    /// Nothing happened yet in the run
+    pub fn new(sim: &Simulation) -> Self {
+        Self {
+            won_at: None,
+            failed: false,
+            touched_targets: sim.touched_targets().to_vec(),
+            time: sim.time,
+            scoreless: false,
+        }
+    }
+
+    /// Follows the run after each of its steps, the setup is only checked to be calm enough
+    /// when the run gets to the win. Returns true once the run failed and nothing else can happen
+    pub fn record(&mut self, sim: &Simulation, result: StepResult, calm: impl FnOnce() -> bool) -> bool {
+        if self.failed {
+            return true;
+        }
+        self.touched_targets.clear();
+        self.touched_targets.extend_from_slice(sim.touched_targets());
+        self.time = sim.time;
+        // falling apart fails the run even after a win, same as in the game
+        let mut failed = result == StepResult::FellApart;
+        if self.won_at.is_none() {
+            // a scoreless run is still checked for the chaos, same as in the game
+            if sim.all_targets_hit() && !calm() {
+                failed = true;
+            } else if sim.all_targets_hit() && !self.scoreless {
+                self.won_at = Some(sim.time);
+            } else if sim.is_broken() {
+                failed = true;
+            }
+        }
+        if failed {
+            self.won_at = None;
+            self.failed = true;
+        }
+        self.failed
+    }
+
+    /// Forgets the steps after the given state of the run, when it was taken back in the game
+    pub fn rewind(&mut self, sim: &Simulation, level: &Level) {
+        let before = |time: f64| time <= sim.time + TIME_STEP / 2.0;
+        if self.failed && before(self.time) {
+            return;
+        }
+        self.scoreless |= level.rewind == Rewind::NoScore;
+        self.won_at = self.won_at.filter(|&time| before(time) && !self.scoreless);
+        self.failed = false;
+        self.touched_targets.clear();
+        self.touched_targets.extend_from_slice(sim.touched_targets());
+        self.time = sim.time;
+    }
+
+    /// Same as the other one, up to the floating point differences between the platforms
+    pub fn matches(&self, other: &Outcome) -> bool {
+        let close = |a: f64, b: f64| (a - b).abs() < TIME_STEP / 2.0;
+        let won_at = match (self.won_at, other.won_at) {
+            (Some(a), Some(b)) => close(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        won_at && self.failed == other.failed && self.scoreless == other.scoreless
+            && self.touched_targets == other.touched_targets
+            && close(self.time, other.time)
+    }
+}
+
+/// Everything needed to repeat a run exactly
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replay {
+    /// The ropes before they are jiggled
+    pub setup: Vec<Rope>,
+    pub seed: u64,
+    pub duration: f64,
+    /// How much of the run was taken back in the game, the steps that were are not in the replay.
+    /// Such a run is played as scoreless on the levels with [Rewind::NoScore]
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub rewound: f64,
+    /// What the player did on each of the steps, nothing after the last one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub controls: Vec<Control>,
+    /// What happened when the run was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<Outcome>,
+}
+
+impl Replay {
+    pub fn new(setup: Vec<Rope>, seed: u64, duration: f64) -> Self {
+        Self {
+            setup,
+            seed,
+            duration,
+            rewound: 0.0,
+            controls: Vec::new(),
+            outcome: None,
+        }
+    }
+
+    /// The setup jiggled the same way every time for the same seed, the game starts its runs with it
+    pub fn jiggled_setup(&self) -> Vec<Rope> {
+        let mut rng = Rng::new(self.seed);
+        let mut ropes = self.setup.clone();
+        for rope in &mut ropes {
+            rope.jiggle(&mut rng);
+        }
+        ropes
+    }
+
+    /// Runs the replay from the start, the observer is called after each of the steps.
+    /// Fails if the setup does not belong to the level
+    pub fn play(&self, level: &Level, mut observer: impl FnMut(&Simulation)) -> Result<Outcome, String> {
+        if self.setup.len() != level.pendulums.len() {
+            return Err(format!("The setup has {} ropes, but the level has {} pendulums", self.setup.len(), level.pendulums.len()));
+        }
+        if let Some(i) = self.setup.iter().zip(&level.pendulums).position(|(rope, p)| rope.root != p.init_state.root) {
+            return Err(format!("Rope {} does not start at the root of its pendulum", i));
+        }
+        // the breaking is not saved with the ropes, it comes from the level
+        let with_breaking = |ropes: Vec<Rope>| ropes.into_iter()
+            .zip(&level.pendulums)
+            .map(|(rope, pendulum)| rope.with_breakable(pendulum.breakable))
+            .collect::<Vec<_>>();
+
+        let mut sim = Simulation::new(level, with_breaking(self.jiggled_setup()));
+        let mut outcome = Outcome::new(&sim);
+        outcome.scoreless = self.rewound > 0.0 && level.rewind == Rewind::NoScore;
+        // a scoreless run gets to the win on every step after it hit all of the targets
+        let mut calm = None;
+        let mut step = 0;
+        while sim.time < self.duration {
+            let control = self.controls.get(step).copied().unwrap_or_default();
+            step += 1;
+            let result = sim.step(level, control);
+            observer(&sim);
+            // the game measures the chaos of the setup before it is jiggled
+            if outcome.record(&sim, result, || *calm.get_or_insert_with(|| lyapunov::within_limit(level, with_breaking(self.setup.clone())))) {
+                break;
+            }
+        }
+        Ok(outcome)
+    }
+
+    /// Plays the replay again to check that it ends the same way as when it was recorded,
+    /// a replay without a recorded outcome has nothing to be checked against and fails
+    pub fn verify(&self, level: &Level) -> Result<Verification, String> {
+        let expected = self.outcome.clone().ok_or_else(|| String::from("The replay has no recorded outcome"))?;
+        let actual = self.play(level, |_| {})?;
+        Ok(Verification { expected, actual })
+    }
+}
+
+/// How a replay ended when it was recorded and when it was played again
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Verification {
+    pub expected: Outcome,
+    pub actual: Outcome,
+}
+
+impl Verification {
+    pub fn is_ok(&self) -> bool {
+        self.expected.matches(&self.actual)
+    }
+}
+
+fn is_zero(value: &f64) -> bool {
+    *value == 0.0
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+#[cfg(test)]
+mod tests {
+    use ld_game_engine::V2;
+
+    use super::*;
+    use crate::{
+        level::test_levels::swinging_level,
+        rope::Breakable,
+    };
+
+    fn recorded(level: &Level) -> Replay {
+        let mut replay = Replay::new(level.init_state(), 42, 3.0);
+        replay.outcome = Some(replay.play(level, |_| {}).unwrap());
+        replay
+    }
+
+    #[test]
+    fn verify_round_trip() {
+        let level = Level::tutorial_level();
+        let replay = recorded(&level);
+        let json = serde_json::to_string(&replay).unwrap();
+        let loaded: Replay = serde_json::from_str(&json).unwrap();
+        let verification = loaded.verify(&level).unwrap();
+        assert!(verification.is_ok());
+        assert_eq!(verification.actual, replay.outcome.unwrap());
+    }
+
+    #[test]
+    fn verify_fails_on_a_different_outcome() {
+        let level = Level::tutorial_level();
+        let mut replay = recorded(&level);
+        replay.outcome.as_mut().unwrap().touched_targets = vec![100];
+        assert!(!replay.verify(&level).unwrap().is_ok());
+    }
+
+    #[test]
+    fn verify_fails_without_an_outcome() {
+        let level = Level::tutorial_level();
+        let replay = Replay::new(level.init_state(), 42, 3.0);
+        assert!(replay.verify(&level).is_err());
+    }
+
+    #[test]
+    fn too_chaotic_setups_fail_at_the_win() {
+        let mut level = swinging_level();
+        let calm = Replay::new(level.init_state(), 42, 3.0).play(&level, |_| {}).unwrap();
+        let won_at = calm.won_at.expect("the target is hit right away");
+
+        level.max_lyapunov = Some(-1000.0);
+        let chaotic = Replay::new(level.init_state(), 42, 3.0).play(&level, |_| {}).unwrap();
+        assert!(chaotic.failed);
+        assert_eq!(chaotic.won_at, None);
+        assert_eq!(chaotic.time, won_at);
+    }
+
+    #[test]
+    fn play_rejects_setups_of_other_levels() {
+        let level = Level::tutorial_level();
+        assert!(Replay::new(Vec::new(), 0, 1.0).play(&level, |_| {}).is_err());
+        let moved = vec![Rope::new(V2::new(1.0, 0.0))];
+        assert!(Replay::new(moved, 0, 1.0).play(&level, |_| {}).is_err());
+    }
+
+    #[test]
+    fn rewound_replays_of_no_score_levels_do_not_win() {
+        let mut level = swinging_level();
+        level.rewind = Rewind::NoScore;
+        let mut replay = Replay::new(level.init_state(), 42, 3.0);
+        replay.rewound = 1.0;
+        let outcome = replay.play(&level, |_| {}).unwrap();
+        assert!(outcome.scoreless);
+        assert_eq!(outcome.won_at, None);
+        assert!(!outcome.failed);
+
+        // a recorded win of such a run is not verified
+        let mut won = outcome.clone();
+        won.won_at = Some(1.0);
+        won.scoreless = false;
+        replay.outcome = Some(won);
+        assert!(!replay.verify(&level).unwrap().is_ok());
+
+        // the time only counts against the win on the other levels
+        level.rewind = Rewind::CountsTime;
+        assert!(replay.play(&level, |_| {}).unwrap().won_at.is_some());
+    }
+
+    /// Steps the run with the outcome recorded until it is done, keeping the state before each of the steps
+    fn run_until(level: &Level, done: impl Fn(&Outcome) -> bool) -> (Outcome, Vec<Simulation>) {
+        let mut sim = Simulation::new(level, level.init_state());
+        let mut outcome = Outcome::new(&sim);
+        let mut snapshots = Vec::new();
+        while !done(&outcome) {
+            assert!(sim.time < 3.0, "the run should be done right away");
+            snapshots.push(sim.clone());
+            let result = sim.step(level, Control::default());
+            outcome.record(&sim, result, || true);
+        }
+        (outcome, snapshots)
+    }
+
+    #[test]
+    fn rewinding_past_the_snap_takes_it_back() {
+        let mut level = swinging_level();
+        level.pendulums[0].breakable = Some(Breakable {
+            threshold: 0.0,
+            steps: 1,
+        });
+        let (mut outcome, snapshots) = run_until(&level, |outcome| outcome.failed);
+        let before = snapshots.last().unwrap();
+        outcome.rewind(before, &level);
+        assert!(!outcome.failed);
+        assert_eq!(outcome.time, before.time);
+        assert_eq!(outcome.won_at, None);
+    }
+
+    #[test]
+    fn rewinding_past_the_win_takes_it_back() {
+        let level = swinging_level();
+        let (outcome, snapshots) = run_until(&level, |outcome| outcome.won_at.is_some());
+        let won_at = outcome.won_at.unwrap();
+
+        // the win stands when the run goes back to a later point
+        let mut later = outcome.clone();
+        let mut sim = snapshots.last().unwrap().clone();
+        sim.step(&level, Control::default());
+        later.rewind(&sim, &level);
+        assert_eq!(later.won_at, Some(won_at));
+
+        let mut rewound = outcome;
+        rewound.rewind(snapshots.last().unwrap(), &level);
+        assert_eq!(rewound.won_at, None);
+        assert_eq!(rewound.touched_targets, vec![0]);
+    }
+}
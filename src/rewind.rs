@@ -0,0 +1,92 @@
+use std::collections::VecDeque;
+
+use crate::sim::Simulation;
+
+/// The states of a run before each of its latest steps, so that it can be taken back
+#[derive(Debug, Clone)]
+pub struct History {
+    snapshots: VecDeque<Simulation>,
+    limit: usize,
+}
+
+impl History {
+    /// Keeps at most the given number of the latest steps
+    pub fn new(limit: usize) -> Self {
+        Self {
+            snapshots: VecDeque::new(),
+            limit,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+
+    /// Should be called before each of the steps of the simulation
+    pub fn record(&mut self, sim: &Simulation) {
+        self.snapshots.push_back(sim.clone());
+        if self.snapshots.len() > self.limit {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Goes back by up to the given number of steps, returning the state
+    /// of the run at that point and how many steps it actually went back
+    pub fn rewind(&mut self, steps: usize) -> Option<(Simulation, usize)> {
+        let steps = steps.min(self.snapshots.len());
+        let snapshot = self.snapshots.drain(self.snapshots.len() - steps..).next()?;
+        Some((snapshot, steps))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        level::Level,
+        sim::{Control, TIME_STEP},
+    };
+
+    /// History of the tutorial run after the given number of steps
+    fn history(steps: usize, limit: usize) -> History {
+        let level = Level::tutorial_level();
+        let mut sim = Simulation::new(&level, level.init_state());
+        let mut history = History::new(limit);
+        for _ in 0..steps {
+            history.record(&sim);
+            sim.step(&level, Control::default());
+        }
+        history
+    }
+
+    fn assert_at_step(sim: &Simulation, step: usize) {
+        assert!((sim.time - step as f64 * TIME_STEP).abs() < 1e-9, "{} is not at step {}", sim.time, step);
+    }
+
+    #[test]
+    fn rewinding_returns_the_state_before_the_taken_back_steps() {
+        let mut history = history(10, 100);
+        let (sim, steps) = history.rewind(3).unwrap();
+        assert_eq!(steps, 3);
+        assert_at_step(&sim, 7);
+
+        // the taken back steps are gone, so it goes on from there
+        let (sim, steps) = history.rewind(2).unwrap();
+        assert_eq!(steps, 2);
+        assert_at_step(&sim, 5);
+    }
+
+    #[test]
+    fn rewinding_stops_at_the_oldest_kept_step() {
+        let mut history = history(10, 4);
+        let (sim, steps) = history.rewind(100).unwrap();
+        assert_eq!(steps, 4);
+        assert_at_step(&sim, 6);
+        assert!(history.is_empty());
+        assert!(history.rewind(1).is_none());
+    }
+}
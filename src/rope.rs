@@ -1,7 +1,13 @@
+use std::{f64::consts::TAU, ops::Add};
+
 use serde::de::Error;
-use serde::Deserializer;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use nalgebra::{DMatrix, DVector};
 
-use ld_game_engine::{util::Mut, V2};
+use ld_game_engine::V2;
+
+use crate::rng::Rng;
 
 #[derive(Debug, Copy, Clone)]
 pub struct Point {
@@ -29,6 +35,21 @@ impl Point {
         self.pos
     }
 
+    pub fn velocity(&self, delta_time: f64) -> V2 {
+        (self.pos - self.prev_pos) / delta_time
+    }
+
+    /// Energy of the point assuming unit mass, locked points are immovable and have none
+    pub fn energy(&self, potential: impl Fn(V2) -> f64, delta_time: f64) -> Energy {
+        if self.locked {
+            return Energy::default();
+        }
+        Energy {
+            kinetic: self.velocity(delta_time).magnitude_squared() / 2.0,
+            potential: potential(self.pos),
+        }
+    }
+
     fn step(&mut self, accel_with_delta_time_sq: V2) {
         if self.locked {
             return;
@@ -37,45 +58,207 @@ impl Point {
         self.pos += (self.pos - self.prev_pos) + accel_with_delta_time_sq;
         self.prev_pos = prev_pos;
     }
+
+    /// Pushes the point out along the normal and reflects the
+    /// part of its (implicit) velocity that was going into the surface
+    fn bounce(&mut self, normal: V2, depth: f64, restitution: f64) {
+        let velocity = self.pos - self.prev_pos;
+        self.pos += normal * depth;
+        let normal_velocity = velocity.dot(&normal);
+        let velocity = if normal_velocity < 0.0 {
+            velocity - normal * normal_velocity * (1.0 + restitution)
+        } else {
+            velocity
+        };
+        self.prev_pos = self.pos - velocity;
+    }
 }
 
-#[derive(Debug)]
-pub struct Constraint {
-    pub point_a: Mut<Point>,
-    pub point_b: Mut<Point>,
-    pub length: f64,
+/// Segments shorter than this are rejected, as the relaxation can not handle
+/// the direction of a segment that has collapsed into a point
+pub const MIN_SEGMENT_LENGTH: f64 = 10.0;
+
+#[derive(Debug, Copy, Clone, Default, Serialize)]
+pub struct Energy {
+    pub kinetic: f64,
+    pub potential: f64,
+}
+
+impl Energy {
+    pub fn total(&self) -> f64 {
+        self.kinetic + self.potential
+    }
+}
+
+impl Add for Energy {
+    type Output = Energy;
+
+    fn add(self, rhs: Energy) -> Energy {
+        Energy {
+            kinetic: self.kinetic + rhs.kinetic,
+            potential: self.potential + rhs.potential,
+        }
+    }
+}
+
+/// A scripted path for the root of the rope, offsets are relative to the
+/// initial root position and are all zero at the start
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RootMotion {
+    Oscillate { amplitude: V2, frequency: f64 },
+    Circle { radius: f64, frequency: f64 },
+    Lissajous { amplitude: V2, frequency: V2 },
 }
 
-impl Clone for Constraint {
-    fn clone(&self) -> Self {
-        Constraint {
-            point_a: Mut::new(*self.point_a.borrow()),
-            point_b: Mut::new(*self.point_b.borrow()),
-            length: self.length,
+impl RootMotion {
+    pub fn offset(&self, time: f64) -> V2 {
+        match *self {
+            RootMotion::Oscillate { amplitude, frequency } => amplitude * (TAU * frequency * time).sin(),
+            RootMotion::Circle { radius, frequency } => {
+                let angle = TAU * frequency * time;
+                V2::new(angle.cos() - 1.0, angle.sin()) * radius
+            }
+            RootMotion::Lissajous { amplitude, frequency } => V2::new(
+                amplitude.x * (TAU * frequency.x * time).sin(),
+                amplitude.y * (TAU * frequency.y * time).sin(),
+            ),
+        }
+    }
+
+    /// How long to trace the path for to show its whole shape,
+    /// zero when the frequency is not positive and there is no shape to show
+    pub fn preview_duration(&self) -> f64 {
+        let (periods, frequency) = match *self {
+            RootMotion::Oscillate { frequency, .. } | RootMotion::Circle { frequency, .. } => (1.0, frequency),
+            RootMotion::Lissajous { frequency, .. } => (4.0, frequency.x.min(frequency.y)),
+        };
+        if frequency.is_finite() && frequency > 0.0 {
+            periods / frequency
+        } else {
+            0.0
         }
     }
 }
 
+/// A solid circle that the rope bounces off during the simulation
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct Collider {
+    pub pos: V2,
+    pub radius: f64,
+    /// 0 makes the rope slide along the surface, 1 is a perfectly elastic bounce
+    pub restitution: f64,
+}
+
+/// How much strain a segment can take before it snaps
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct Breakable {
+    /// The largest length correction per relaxation the segment can withstand
+    pub threshold: f64,
+    /// How many simulation steps in a row over the threshold it takes to snap
+    pub steps: u32,
+}
+
+/// A segment between two points of the rope, referred to by their indices in [Rope::points]
+#[derive(Debug, Copy, Clone)]
+pub struct Constraint {
+    pub point_a: usize,
+    pub point_b: usize,
+    pub length: f64,
+    pub breakable: Option<Breakable>,
+    pub broken: bool,
+    strain: f64,
+    strained_steps: u32,
+}
+
 impl Constraint {
-    fn relax(&mut self) {
-        let mut point_a = self.point_a.borrow_mut();
-        let mut point_b = self.point_b.borrow_mut();
+    fn relax(&mut self, points: &mut [Point]) {
+        if self.broken {
+            return;
+        }
+        let mut point_a = points[self.point_a];
+        let mut point_b = points[self.point_b];
         let diff = point_a.pos - point_b.pos;
-        let direction = diff.normalize();
-        let delta_d = diff.magnitude() - self.length;
+        let distance = diff.magnitude();
+        if distance < f64::EPSILON {
+            // the direction is undefined, the neighbouring segments will pull the points apart
+            return;
+        }
+        let direction = diff / distance;
+        let delta_d = distance - self.length;
         if !point_a.locked {
             point_a.pos -= direction * delta_d / 2.0
         }
         if !point_b.locked {
             point_b.pos += direction * delta_d / 2.0
         }
+        self.strain = self.strain.max(delta_d.abs());
+        points[self.point_a] = point_a;
+        points[self.point_b] = point_b;
+    }
+
+    fn update_strain(&mut self) {
+        if let Some(breakable) = self.breakable {
+            if self.strain > breakable.threshold {
+                self.strained_steps += 1;
+                if self.strained_steps >= breakable.steps {
+                    self.broken = true;
+                }
+            } else {
+                self.strained_steps = 0;
+            }
+        }
+        self.strain = 0.0;
+    }
+
+    fn collide(&self, collider: &Collider, points: &mut [Point]) {
+        if self.broken {
+            return;
+        }
+        let mut point_a = points[self.point_a];
+        let mut point_b = points[self.point_b];
+
+        let segment = point_b.pos - point_a.pos;
+        let length_sq = segment.magnitude_squared();
+        let t = if length_sq > 0.0 {
+            ((collider.pos - point_a.pos).dot(&segment) / length_sq).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let diff = point_a.pos + segment * t - collider.pos;
+        let distance = diff.magnitude();
+        if distance >= collider.radius || distance == 0.0 {
+            return;
+        }
+        let normal = diff / distance;
+        let depth = collider.radius - distance;
+
+        // distribute the correction so that the closest point ends up on the surface
+        let weight_a = if point_a.locked { 0.0 } else { 1.0 - t };
+        let weight_b = if point_b.locked { 0.0 } else { t };
+        let total = weight_a * weight_a + weight_b * weight_b;
+        if total == 0.0 {
+            return;
+        }
+        if weight_a > 0.0 {
+            point_a.bounce(normal, depth * weight_a / total, collider.restitution);
+        }
+        if weight_b > 0.0 {
+            point_b.bounce(normal, depth * weight_b / total, collider.restitution);
+        }
+        points[self.point_a] = point_a;
+        points[self.point_b] = point_b;
     }
 }
 
-#[derive(Debug)]
+/// The points and the segments between them are stored in flat arrays,
+/// the first point is always the locked root one
+#[derive(Debug, Clone)]
 pub struct Rope {
     pub root: V2,
+    points: Vec<Point>,
     pub constraints: Vec<Constraint>,
+    breakable: Option<Breakable>,
 }
 
 impl<'de> serde::Deserialize<'de> for Rope {
@@ -85,69 +268,363 @@ impl<'de> serde::Deserialize<'de> for Rope {
             return Err(D::Error::custom("Empty rope"));
         }
         let mut rope = Rope::new(result[0]);
-        for point in result.iter().skip(1).copied() {
-            rope.add(point);
+        for (i, point) in result.iter().skip(1).copied().enumerate() {
+            if !rope.add(point) {
+                return Err(D::Error::custom(format!(
+                    "Segment {} is shorter than the minimum of {}",
+                    i, MIN_SEGMENT_LENGTH,
+                )));
+            }
         }
         Ok(rope)
     }
 }
 
+impl Serialize for Rope {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.points.iter().map(|p| p.pos))
+    }
+}
+
 impl Rope {
     pub fn new(root: V2) -> Rope {
         Rope {
             root,
+            points: vec![Point::locked(root)],
             constraints: Vec::new(),
+            breakable: None,
         }
     }
 
-    pub fn add(&mut self, point: V2) {
-        let point_a = self
-            .constraints
-            .last()
-            .map(|s| s.point_b.clone())
-            .unwrap_or_else(|| Mut::new(Point::locked(self.root)));
-        let length = (point - point_a.borrow().pos).magnitude();
-        let point_b = Mut::new(Point::new(point));
+    /// Makes all of the segments, including the ones added later, breakable
+    pub fn with_breakable(mut self, breakable: Option<Breakable>) -> Self {
+        self.breakable = breakable;
+        for constraint in &mut self.constraints {
+            constraint.breakable = breakable;
+        }
+        self
+    }
+
+    /// Adds a segment from the tail to the given point,
+    /// returns false and does nothing if the segment would be too short
+    pub fn add(&mut self, point: V2) -> bool {
+        let last = self.points.len() - 1;
+        let length = (point - self.points[last].pos).magnitude();
+        if !point.iter().all(|c| c.is_finite()) || length < MIN_SEGMENT_LENGTH {
+            return false;
+        }
+        self.points.push(Point::new(point));
         self.constraints.push(Constraint {
-            point_a,
-            point_b,
+            point_a: last,
+            point_b: last + 1,
             length,
-        })
+            breakable: self.breakable,
+            broken: false,
+            strain: 0.0,
+            strained_steps: 0,
+        });
+        true
+    }
+
+    /// Moves the locked first point, the rest of the rope
+    /// follows it through the constraints on the next simulation step
+    pub fn move_root(&mut self, root: V2) {
+        self.root = root;
+        let point = &mut self.points[0];
+        point.prev_pos = point.pos;
+        point.pos = root;
     }
 
+    /// The end of the part of the rope that is still attached to the root
     pub fn tail(&self) -> V2 {
         self.constraints
+            .iter()
+            .take_while(|s| !s.broken)
             .last()
-            .map(|s| s.point_b.borrow().pos)
+            .map(|s| self.points[s.point_b].pos)
             .unwrap_or(self.root)
     }
 
-    pub fn jiggle(&mut self) {
-        for constraint in &self.constraints {
-            let x = js_sys::Math::random() - 0.5;
-            let y = js_sys::Math::random() - 0.5;
-            constraint.point_b.borrow_mut().pos += V2::from([x, y]);
+    /// All of the points, starting with the root one
+    pub fn points(&self) -> &[Point] {
+        &self.points
+    }
+
+    pub fn energies(&self, potential: impl Fn(V2) -> f64, delta_time: f64) -> Vec<Energy> {
+        self.points
+            .iter()
+            .map(|point| point.energy(&potential, delta_time))
+            .collect()
+    }
+
+    pub fn energy(&self, potential: impl Fn(V2) -> f64, delta_time: f64) -> Energy {
+        self.energies(potential, delta_time)
+            .into_iter()
+            .fold(Energy::default(), Add::add)
+    }
+
+    /// Rescales the velocities so that the total energy moves towards the given one,
+    /// compensating for the drift of the integration
+    pub fn correct_energy(&mut self, target: f64, potential: impl Fn(V2) -> f64, delta_time: f64) {
+        let energy = self.energy(&potential, delta_time);
+        let wanted_kinetic = target - energy.potential;
+        if energy.kinetic <= 0.0 || wanted_kinetic <= 0.0 {
+            return;
+        }
+        // correct gradually so that there are no visible jerks
+        let scale = (wanted_kinetic / energy.kinetic).sqrt().clamp(0.95, 1.05);
+        for point in self.points.iter_mut().filter(|p| !p.locked) {
+            point.prev_pos = point.pos - (point.pos - point.prev_pos) * scale;
         }
     }
 
-    pub fn simulate(&mut self, gravity: V2, delta_time: f64, num_iterations: u32) {
-        for constraint in &mut self.constraints {
-            let accel = gravity * (delta_time * delta_time);
-            constraint.point_a.borrow_mut().step(accel);
-            constraint.point_b.borrow_mut().step(accel);
+    /// Angle of the segment from the downward vertical, counter-clockwise in radians,
+    /// and how fast it changes
+    pub fn segment_phase(&self, index: usize, delta_time: f64) -> (f64, f64) {
+        let constraint = &self.constraints[index];
+        let (a, b) = (&self.points[constraint.point_a], &self.points[constraint.point_b]);
+        let d = b.pos - a.pos;
+        let dv = b.velocity(delta_time) - a.velocity(delta_time);
+        (d.x.atan2(d.y), (d.y * dv.x - d.x * dv.y) / d.magnitude_squared())
+    }
+
+    /// Distance to the state of the other rope with the same points,
+    /// with both the current and the previous positions as the coordinates
+    pub fn separation(&self, other: &Rope) -> f64 {
+        self.points.iter()
+            .zip(&other.points)
+            .map(|(a, b)| (a.pos - b.pos).magnitude_squared() + (a.prev_pos - b.prev_pos).magnitude_squared())
+            .sum::<f64>()
+            .sqrt()
+    }
+
+    /// Scales the difference from the state of the other rope by the given factor
+    pub fn rescale_from(&mut self, other: &Rope, factor: f64) {
+        for (a, b) in self.points.iter_mut().zip(&other.points) {
+            a.pos = b.pos + (a.pos - b.pos) * factor;
+            a.prev_pos = b.prev_pos + (a.prev_pos - b.prev_pos) * factor;
         }
+    }
+
+    /// False if the simulation blew up and some of the positions became NaN or infinite
+    pub fn is_finite(&self) -> bool {
+        self.root.iter().all(|c| c.is_finite())
+            && self.points
+                .iter()
+                .all(|point| point.pos.iter().chain(point.prev_pos.iter()).all(|c| c.is_finite()))
+    }
+
+    pub fn is_broken(&self) -> bool {
+        self.constraints.iter().any(|s| s.broken)
+    }
+
+    /// The slight nudge every run of the game starts with
+    pub fn jiggle(&mut self, rng: &mut Rng) {
+        self.jiggle_by(rng, 1.0);
+    }
+
+    /// Moves each of the free points by up to half of the given magnitude along each axis
+    pub fn jiggle_by(&mut self, rng: &mut Rng, magnitude: f64) {
+        for point in self.points.iter_mut().filter(|p| !p.locked) {
+            let x = rng.next_f64() - 0.5;
+            let y = rng.next_f64() - 0.5;
+            point.pos += V2::from([x, y]) * magnitude;
+        }
+    }
+
+    /// The acceleration is evaluated separately for each point,
+    /// given its position and velocity
+    pub fn simulate(&mut self, accel: impl Fn(V2, V2) -> V2, colliders: &[Collider], delta_time: f64, num_iterations: u32) {
+        let delta_time_sq = delta_time * delta_time;
+        for point in self.points.iter_mut().filter(|p| !p.locked) {
+            let velocity = if delta_time > 0.0 {
+                point.velocity(delta_time)
+            } else {
+                V2::zeros()
+            };
+            let accel = accel(point.pos, velocity) * delta_time_sq;
+            point.step(accel);
+        }
+        let points = &mut self.points;
         for _ in 0..num_iterations {
-            self.constraints.iter_mut().for_each(Constraint::relax);
+            for constraint in &mut self.constraints {
+                constraint.relax(points);
+            }
+            for constraint in &self.constraints {
+                for collider in colliders {
+                    constraint.collide(collider, points);
+                }
+            }
+        }
+        self.constraints.iter_mut().for_each(Constraint::update_strain);
+    }
+
+    /// Integrates the rope as a rigid N-pendulum with equal point masses at the joints,
+    /// using RK4 in the segment angle coordinates.
+    ///
+    /// Unlike [Rope::simulate] this behaves like the actual physical system,
+    /// but it assumes that the root is fixed and ignores colliders and breaking.
+    pub fn simulate_exact(&mut self, accel: impl Fn(V2, V2) -> V2, delta_time: f64, substeps: u32) {
+        if self.constraints.is_empty() || delta_time <= 0.0 {
+            return;
+        }
+        let lengths = self.constraints.iter().map(|c| c.length).collect::<Vec<_>>();
+        let n = lengths.len();
+
+        // recover the angles and angular velocities from the verlet state
+        let mut state = DVector::zeros(2 * n);
+        let mut prev = (self.root, V2::zeros());
+        for (i, constraint) in self.constraints.iter().enumerate() {
+            let point = &self.points[constraint.point_b];
+            let velocity = point.velocity(delta_time);
+            let diff = point.pos - prev.0;
+            let angle = diff.y.atan2(diff.x);
+            state[i] = angle;
+            state[n + i] = (velocity - prev.1).dot(&V2::new(-angle.sin(), angle.cos())) / lengths[i];
+            prev = (point.pos, velocity);
+        }
+
+        let derivative = |state: &DVector<f64>| {
+            let mut result = DVector::zeros(2 * n);
+            result.rows_mut(0, n).copy_from(&state.rows(n, n));
+            result.rows_mut(n, n).copy_from(&angular_accel(self.root, &lengths, state, &accel));
+            result
+        };
+        let h = delta_time / substeps as f64;
+        for _ in 0..substeps {
+            let k1 = derivative(&state);
+            let k2 = derivative(&(&state + &k1 * (h / 2.0)));
+            let k3 = derivative(&(&state + &k2 * (h / 2.0)));
+            let k4 = derivative(&(&state + &k3 * h));
+            state += (k1 + k2 * 2.0 + k3 * 2.0 + k4) * (h / 6.0);
+        }
+
+        for (constraint, (pos, velocity)) in self.constraints.iter().zip(joints(self.root, &lengths, &state)) {
+            let point = &mut self.points[constraint.point_b];
+            point.pos = pos;
+            point.prev_pos = pos - velocity * delta_time;
         }
     }
 }
 
-impl Clone for Rope {
-    fn clone(&self) -> Self {
-        let mut new_rope = Rope::new(self.root);
-        for constraint in &self.constraints {
-            new_rope.add(constraint.point_b.borrow().pos);
+/// Positions and velocities of the joints given the angle coordinates
+fn joints(root: V2, lengths: &[f64], state: &DVector<f64>) -> Vec<(V2, V2)> {
+    let n = lengths.len();
+    let mut pos = root;
+    let mut velocity = V2::zeros();
+    lengths.iter()
+        .enumerate()
+        .map(|(i, &length)| {
+            let (sin, cos) = state[i].sin_cos();
+            pos += V2::new(cos, sin) * length;
+            velocity += V2::new(-sin, cos) * length * state[n + i];
+            (pos, velocity)
+        })
+        .collect()
+}
+
+/// Solves the Lagrange equations of the N-pendulum for the angular accelerations
+fn angular_accel(root: V2, lengths: &[f64], state: &DVector<f64>, accel: impl Fn(V2, V2) -> V2) -> DVector<f64> {
+    let n = lengths.len();
+    let angle = |i: usize| state[i];
+    let omega = |i: usize| state[n + i];
+    // how many joints (with unit masses) are moved by both segment i and segment j
+    let mass = |i: usize, j: usize| (n - i.max(j)) as f64;
+
+    // total external force on everything hanging from each segment
+    let mut forces = joints(root, lengths, state)
+        .into_iter()
+        .map(|(pos, velocity)| accel(pos, velocity))
+        .collect::<Vec<_>>();
+    for i in (0..n - 1).rev() {
+        forces[i] = forces[i] + forces[i + 1];
+    }
+
+    let matrix = DMatrix::from_fn(n, n, |i, j| mass(i, j) * lengths[j] * (angle(i) - angle(j)).cos());
+    let rhs = DVector::from_fn(n, |i, _| {
+        let normal = V2::new(-angle(i).sin(), angle(i).cos());
+        let centripetal = (0..n)
+            .map(|j| mass(i, j) * lengths[j] * omega(j) * omega(j) * (angle(j) - angle(i)).sin())
+            .sum::<f64>();
+        forces[i].dot(&normal) + centripetal
+    });
+    matrix.lu().solve(&rhs).unwrap_or_else(|| DVector::zeros(n))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::test_levels::double_pendulum;
+
+    #[test]
+    fn add_rejects_too_short_segments() {
+        let mut rope = Rope::new(V2::new(0.0, 0.0));
+        assert!(!rope.add(V2::new(MIN_SEGMENT_LENGTH / 2.0, 0.0)));
+        assert_eq!(rope.points().len(), 1);
+        assert!(rope.constraints.is_empty());
+
+        assert!(rope.add(V2::new(MIN_SEGMENT_LENGTH, 0.0)));
+        assert_eq!(rope.points().len(), 2);
+        assert_eq!(rope.constraints[0].length, MIN_SEGMENT_LENGTH);
+    }
+
+    #[test]
+    fn add_rejects_non_finite_points() {
+        let mut rope = Rope::new(V2::new(0.0, 0.0));
+        assert!(!rope.add(V2::new(f64::NAN, 100.0)));
+        assert!(!rope.add(V2::new(f64::INFINITY, 0.0)));
+        assert_eq!(rope.points().len(), 1);
+    }
+
+    #[test]
+    fn deserializing_rejects_too_short_segments() {
+        assert!(serde_json::from_str::<Rope>("[[0, 0], [1, 0]]").is_err());
+        assert!(serde_json::from_str::<Rope>("[]").is_err());
+        let rope: Rope = serde_json::from_str("[[0, 0], [0, 100]]").unwrap();
+        assert_eq!(rope.tail(), V2::new(0.0, 100.0));
+    }
+
+    const GRAVITY: V2 = V2::new(0.0, 1000.0);
+    const DELTA_TIME: f64 = 1.0 / 60.0;
+
+    fn potential(pos: V2) -> f64 {
+        -GRAVITY.dot(&pos)
+    }
+
+    #[test]
+    fn exact_solver_conserves_energy() {
+        let mut rope = double_pendulum();
+        let reference = rope.energy(potential, DELTA_TIME).total();
+        let mut peak_kinetic: f64 = 0.0;
+        // a minute of the game
+        for _ in 0..60 * 60 {
+            rope.simulate_exact(|_, _| GRAVITY, DELTA_TIME, 8);
+            let energy = rope.energy(potential, DELTA_TIME);
+            peak_kinetic = peak_kinetic.max(energy.kinetic);
+            assert!((energy.total() - reference).abs() < 1e-4 * peak_kinetic.max(1.0));
+        }
+        assert!(peak_kinetic > 0.0);
+    }
+
+    #[test]
+    fn energy_correction_keeps_the_total_energy() {
+        let mut rope = double_pendulum();
+        let reference = rope.energy(potential, DELTA_TIME).total();
+        let mut peak_kinetic: f64 = 0.0;
+        let mut off = 0;
+        let steps = 60 * 60;
+        for _ in 0..steps {
+            rope.simulate(|_, _| GRAVITY, &[], DELTA_TIME, 15);
+            rope.correct_energy(reference, potential, DELTA_TIME);
+            let energy = rope.energy(potential, DELTA_TIME);
+            peak_kinetic = peak_kinetic.max(energy.kinetic);
+            // the correction is clamped, so it never pushes the energy far past the reference
+            assert!(energy.total() - reference < 0.02 * peak_kinetic.max(1.0));
+            if reference - energy.total() > 0.01 * peak_kinetic {
+                off += 1;
+            }
         }
-        new_rope
+        // the relaxation loses energy in bursts and it takes a few steps to get it back
+        assert!(off < steps / 5, "{} of {} steps were off", off, steps);
     }
 }
@@ -0,0 +1,240 @@
+use std::f64::consts::TAU;
+
+use ld_game_engine::V2;
+
+use crate::{
+    estimate::{Estimate, Estimator},
+    level::Level,
+    lyapunov,
+    rng::Rng,
+    rope::{Rope, MIN_SEGMENT_LENGTH},
+};
+
+/// How hard the search tries, the defaults take about ten seconds per level natively
+/// and find a setup that wins at least half of the time on each of the campaign levels.
+/// Roots on rails are never moved, so the levels with them are harder than they look to it
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    /// At most this many segments are added to each of the pendulums
+    pub max_segments: usize,
+    /// Players stretch the segments across the level, some of the targets are out of reach with much shorter ones
+    pub max_segment_length: f64,
+    /// Random setups tried before refining the best one
+    pub candidates: usize,
+    /// Attempts to nudge the points of the best setup around
+    pub refinements: usize,
+    /// Jiggled runs each of the setups is estimated with while searching
+    pub runs: usize,
+    /// Jiggled runs the best setup is estimated with in the end
+    pub final_runs: usize,
+    /// Length of each run in seconds
+    pub duration: f64,
+    pub seed: u64,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            max_segments: 3,
+            max_segment_length: 800.0,
+            candidates: 400,
+            refinements: 200,
+            runs: 16,
+            final_runs: 200,
+            duration: 20.0,
+            seed: 0,
+        }
+    }
+}
+
+/// A setup that is reachable in the game
+#[derive(Debug, Clone)]
+pub struct Solution {
+    /// Points the player adds to each of the pendulums, in order
+    pub added: Vec<Vec<V2>>,
+    pub ropes: Vec<Rope>,
+    pub estimate: Estimate,
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub best: Solution,
+    /// How often a random setup wins on average, the lower it is the harder the level
+    pub random_win_rate: f64,
+}
+
+impl Report {
+    pub fn is_solvable(&self, min_win_rate: f64) -> bool {
+        self.best.estimate.win_rate() >= min_win_rate
+    }
+}
+
+/// Looks for the setup that wins most often, first trying random ones and then refining the best
+pub fn search(level: &Level, options: &SearchOptions) -> Report {
+    let mut rng = Rng::new(options.seed);
+
+    let mut best = evaluate(level, vec![Vec::new(); level.pendulums.len()], options.runs, options, &mut rng)
+        .expect("the initial state of a valid level is always buildable");
+    let mut total_win_rate = 0.0;
+    let mut tried = 0;
+
+    for _ in 0..options.candidates {
+        let added = random_setup(level, options, &mut rng);
+        if let Some(candidate) = evaluate(level, added, options.runs, options, &mut rng) {
+            total_win_rate += candidate.estimate.win_rate();
+            tried += 1;
+            if score(&candidate.estimate) > score(&best.estimate) {
+                best = candidate;
+            }
+        }
+    }
+
+    for i in 0..options.refinements {
+        // start with big nudges and gradually make them smaller
+        let radius = options.max_segment_length / 4.0 * (1.0 - i as f64 / options.refinements as f64) + 5.0;
+        let mut added = best.added.clone();
+        let count = added.iter().map(Vec::len).sum::<usize>();
+        if count == 0 {
+            break;
+        }
+        let mut index = (rng.next_f64() * count as f64) as usize;
+        for points in &mut added {
+            if index < points.len() {
+                let angle = rng.next_f64() * TAU;
+                // same as the random setups, so that the reported points are the ones that are used
+                points[index] = level.constrain(points[index] + V2::new(angle.cos(), angle.sin()) * radius * rng.next_f64());
+                break;
+            }
+            index -= points.len();
+        }
+        if let Some(candidate) = evaluate(level, added, options.runs, options, &mut rng) {
+            if score(&candidate.estimate) > score(&best.estimate) {
+                best = candidate;
+            }
+        }
+    }
+
+    let best = evaluate(level, best.added, options.final_runs, options, &mut rng)
+        .expect("the best setup was buildable before");
+    Report {
+        best,
+        random_win_rate: if tried == 0 { 0.0 } else { total_win_rate / tried as f64 },
+    }
+}
+
+/// Adds the given points to the initial state the same way the player does,
+/// none if some of the segments end up too short
+pub fn build(level: &Level, added: &[Vec<V2>]) -> Option<Vec<Rope>> {
+    let mut ropes = level.init_state();
+    for (rope, points) in ropes.iter_mut().zip(added) {
+        for &point in points {
+            if !rope.add(level.constrain(point)) {
+                return None;
+            }
+        }
+    }
+    Some(ropes)
+}
+
+fn random_setup(level: &Level, options: &SearchOptions, rng: &mut Rng) -> Vec<Vec<V2>> {
+    let min_length = MIN_SEGMENT_LENGTH * 5.0;
+    level.init_state()
+        .iter()
+        .map(|rope| {
+            let count = (rng.next_f64() * (options.max_segments + 1) as f64) as usize;
+            let mut tail = rope.tail();
+            (0..count)
+                .map(|_| {
+                    let angle = rng.next_f64() * TAU;
+                    let length = min_length + rng.next_f64() * (options.max_segment_length - min_length);
+                    tail = level.constrain(tail + V2::new(angle.cos(), angle.sin()) * length);
+                    tail
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn evaluate(level: &Level, added: Vec<Vec<V2>>, runs: usize, options: &SearchOptions, rng: &mut Rng) -> Option<Solution> {
+    let ropes = build(level, &added)?;
+    // too chaotic setups never win, same as in the game
+    let estimate = if lyapunov::within_limit(level, ropes.clone()) {
+        Estimator::new(ropes.clone(), runs, options.duration, rng.fork()).run(level)
+    } else {
+        Estimate::default()
+    };
+    Some(Solution { added, ropes, estimate })
+}
+
+/// Wins more often first, then gets closer to winning, then wins faster
+fn score(estimate: &Estimate) -> f64 {
+    let time = estimate.win_time_quantile(0.5).unwrap_or(0.0);
+    estimate.win_rate() + estimate.mean_progress() * 1e-2 - time * 1e-6
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quick_options() -> SearchOptions {
+        SearchOptions {
+            candidates: 4,
+            refinements: 4,
+            runs: 2,
+            final_runs: 4,
+            duration: 2.0,
+            ..SearchOptions::default()
+        }
+    }
+
+    #[test]
+    fn build_moves_the_points_out_of_the_targets() {
+        let level = Level::tutorial_level();
+        let ropes = build(&level, &[vec![V2::new(100.0, 0.0)]]).unwrap();
+        assert_eq!(ropes[0].tail(), V2::new(300.0, 0.0));
+    }
+
+    #[test]
+    fn build_rejects_too_short_segments() {
+        let level = Level::tutorial_level();
+        assert!(build(&level, &[vec![V2::new(0.0, -301.0)]]).is_none());
+    }
+
+    #[test]
+    fn same_seed_finds_the_same_setup() {
+        let level = Level::tutorial_level();
+        let a = search(&level, &quick_options());
+        let b = search(&level, &quick_options());
+        assert_eq!(a.best.added, b.best.added);
+        assert_eq!(a.best.estimate.wins, b.best.estimate.wins);
+        assert_eq!(a.best.estimate.runs, 4);
+    }
+
+    #[test]
+    fn first_levels_are_solvable() {
+        // as far as the defaults reach, but with fewer and shorter runs, these levels are won within seconds
+        let options = SearchOptions {
+            candidates: 200,
+            refinements: 20,
+            runs: 4,
+            final_runs: 20,
+            duration: 5.0,
+            ..SearchOptions::default()
+        };
+        for level in &[Level::tutorial_level(), Level::second_level()] {
+            assert!(search(level, &options).is_solvable(0.5));
+        }
+    }
+
+    #[test]
+    fn found_points_are_outside_of_the_closed_areas() {
+        let level = Level::tutorial_level();
+        let report = search(&level, &SearchOptions {
+            refinements: 32,
+            ..quick_options()
+        });
+        for &point in report.best.added.iter().flatten() {
+            assert_eq!(level.constrain(point), point);
+        }
+    }
+}
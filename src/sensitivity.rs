@@ -0,0 +1,225 @@
+use std::f64::consts::{PI, TAU};
+
+use ld_game_engine::v2;
+
+use crate::{
+    level::Level,
+    rope::Rope,
+    sim::{Control, Simulation, StepResult, TIME_STEP},
+};
+
+/// What the cells of the map are coloured by
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Metric {
+    /// Time until any of the two segments swings over the top
+    FirstFlip,
+    /// Time until all of the targets are hit
+    Win,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Cell {
+    Pending,
+    /// Some of the points are where the player can not put them
+    Forbidden,
+    /// When the metric happened, none if it did not during the run
+    Done(Option<f64>),
+}
+
+/// Sweeps the initial angles of both of the segments of a two-segment pendulum over a grid,
+/// running each of them without any jiggle, to show how sensitive the outcome is to them.
+/// Same as with the [Estimator](crate::estimate::Estimator) the work can be done over several frames
+#[derive(Debug, Clone)]
+pub struct Sensitivity {
+    setup: Vec<Rope>,
+    pub pendulum: usize,
+    pub resolution: usize,
+    pub metric: Metric,
+    duration: f64,
+    cells: Vec<Cell>,
+    next: usize,
+    current: Option<(Simulation, Vec<f64>)>,
+}
+
+impl Sensitivity {
+    /// The other pendulums keep their setup,
+    /// none if the chosen one does not have exactly two segments
+    pub fn new(setup: Vec<Rope>, pendulum: usize, resolution: usize, metric: Metric, duration: f64) -> Option<Self> {
+        if setup.get(pendulum)?.constraints.len() != 2 {
+            return None;
+        }
+        Some(Self {
+            setup,
+            pendulum,
+            resolution,
+            metric,
+            duration,
+            cells: vec![Cell::Pending; resolution * resolution],
+            next: 0,
+            current: None,
+        })
+    }
+
+    /// The cells row by row, the first segment angle goes along the rows
+    /// and the second one along the columns, both from -π to π
+    pub fn cells(&self) -> &[Cell] {
+        &self.cells
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.next >= self.cells.len()
+    }
+
+    /// Fraction of the cells that are done
+    pub fn progress(&self) -> f64 {
+        self.next as f64 / self.cells.len() as f64
+    }
+
+    /// Initial angles of both of the segments in the given cell, from the downward vertical
+    pub fn angles(&self, x: usize, y: usize) -> (f64, f64) {
+        let angle = |i: usize| (i as f64 + 0.5) / self.resolution as f64 * TAU - PI;
+        (angle(x), angle(y))
+    }
+
+    /// The setup with the chosen pendulum at the angles of the given cell
+    pub fn setup(&self, level: &Level, x: usize, y: usize) -> Option<Vec<Rope>> {
+        let (first, second) = self.angles(x, y);
+        let template = &self.setup[self.pendulum];
+        let mut rope = Rope::new(template.root)
+            .with_breakable(level.pendulums[self.pendulum].breakable);
+        let mut tail = template.root;
+        for (constraint, angle) in template.constraints.iter().zip([first, second].iter()) {
+            tail += v2![angle.sin(), angle.cos()] * constraint.length;
+            if level.constrain(tail) != tail || !rope.add(tail) {
+                return None;
+            }
+        }
+        let mut setup = self.setup.clone();
+        setup[self.pendulum] = rope;
+        Some(setup)
+    }
+
+    /// Does at most the given number of simulation steps
+    pub fn advance(&mut self, level: &Level, max_steps: usize) {
+        let mut steps = 0;
+        while steps < max_steps && !self.is_done() {
+            let (sim, angles) = match &mut self.current {
+                Some(current) => current,
+                None => {
+                    let (x, y) = (self.next % self.resolution, self.next / self.resolution);
+                    match self.setup(level, x, y) {
+                        Some(setup) => {
+                            let sim = Simulation::new(level, setup);
+                            let angles = self.segment_angles(&sim);
+                            self.current.get_or_insert((sim, angles))
+                        }
+                        None => {
+                            self.finish(Cell::Forbidden);
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            steps += 1;
+            if sim.step(level, Control::default()) == StepResult::FellApart {
+                self.finish(Cell::Done(None));
+                continue;
+            }
+            let happened = match self.metric {
+                Metric::FirstFlip => {
+                    let rope = &sim.ropes[self.pendulum];
+                    let mut flipped = false;
+                    for (i, prev) in angles.iter_mut().enumerate() {
+                        let (angle, _) = rope.segment_phase(i, TIME_STEP);
+                        // going over the top is the jump between π and -π
+                        flipped |= (angle - *prev).abs() > PI;
+                        *prev = angle;
+                    }
+                    flipped
+                }
+                Metric::Win => sim.all_targets_hit(),
+            };
+            if happened {
+                let time = sim.time;
+                self.finish(Cell::Done(Some(time)));
+            } else if sim.time >= self.duration {
+                self.finish(Cell::Done(None));
+            }
+        }
+    }
+
+    fn segment_angles(&self, sim: &Simulation) -> Vec<f64> {
+        let rope = &sim.ropes[self.pendulum];
+        (0..rope.constraints.len())
+            .map(|i| rope.segment_phase(i, TIME_STEP).0)
+            .collect()
+    }
+
+    fn finish(&mut self, cell: Cell) {
+        self.cells[self.next] = cell;
+        self.next += 1;
+        self.current = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::{test_levels::double_pendulum_level, Circle};
+
+    #[test]
+    fn only_two_segment_pendulums_are_swept() {
+        let level = double_pendulum_level();
+        assert!(Sensitivity::new(level.init_state(), 0, 4, Metric::FirstFlip, 1.0).is_some());
+        assert!(Sensitivity::new(level.init_state(), 1, 4, Metric::FirstFlip, 1.0).is_none());
+
+        let mut single = Rope::new(v2![0.0, 0.0]);
+        single.add(v2![0.0, 100.0]);
+        assert!(Sensitivity::new(vec![single], 0, 4, Metric::FirstFlip, 1.0).is_none());
+    }
+
+    #[test]
+    fn cell_setups_have_the_cell_angles() {
+        let level = double_pendulum_level();
+        let sensitivity = Sensitivity::new(level.init_state(), 0, 8, Metric::FirstFlip, 1.0).unwrap();
+        for &(x, y) in &[(0, 0), (3, 5), (7, 2)] {
+            let setup = sensitivity.setup(&level, x, y).unwrap();
+            let (first, second) = sensitivity.angles(x, y);
+            let rope = &setup[0];
+            assert_eq!(rope.root, v2![0.0, 0.0]);
+            assert!((rope.segment_phase(0, TIME_STEP).0 - first).abs() < 1e-9);
+            assert!((rope.segment_phase(1, TIME_STEP).0 - second).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn cells_in_closed_areas_are_forbidden() {
+        let mut level = double_pendulum_level();
+        // everything but the root is covered
+        level.red_zones.push(Circle {
+            pos: v2![0.0, 0.0],
+            radius: 1000.0,
+        });
+        let mut sensitivity = Sensitivity::new(level.init_state(), 0, 4, Metric::FirstFlip, 1.0).unwrap();
+        assert!(sensitivity.setup(&level, 1, 2).is_none());
+        sensitivity.advance(&level, 1000);
+        assert!(sensitivity.is_done());
+        assert!(sensitivity.cells().iter().all(|&cell| cell == Cell::Forbidden));
+    }
+
+    #[test]
+    fn every_cell_gets_done() {
+        let level = double_pendulum_level();
+        let mut sensitivity = Sensitivity::new(level.init_state(), 0, 4, Metric::FirstFlip, 0.5).unwrap();
+        while !sensitivity.is_done() {
+            sensitivity.advance(&level, 100);
+        }
+        assert_eq!(sensitivity.progress(), 1.0);
+        assert!(sensitivity.cells().iter().all(|cell| matches!(cell, Cell::Done(_))));
+    }
+}
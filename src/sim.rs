@@ -0,0 +1,219 @@
+use serde::*;
+
+use ld_game_engine::V2;
+
+use crate::{
+    level::{Level, Solver},
+    rope::{Energy, Rope},
+};
+
+/// The simulation always advances in steps of this size, regardless of the frame rate
+pub const TIME_STEP: f64 = 1.0 / 60.0;
+
+/// How fast the player can move the root, in units per second
+pub const ROOT_SPEED: f64 = 800.0;
+
+/// What the player does with the roots that are on rails
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Control {
+    /// Where the roots are dragged to with the mouse
+    pub steering: Option<V2>,
+    /// Direction the roots are moved in with the arrow keys
+    pub arrows: V2,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StepResult {
+    Nothing,
+    /// A pendulum has just entered a target
+    Hit,
+    /// The simulation blew up, the ropes are left in a broken state
+    FellApart,
+}
+
+/// A single run of a level, with no rendering or input handling attached
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    pub ropes: Vec<Rope>,
+    pub time: f64,
+    touched_targets: Box<[usize]>,
+    touching_targets: Box<[Option<usize>]>,
+    reference_energy: Vec<f64>,
+}
+
+impl Simulation {
+    pub fn new(level: &Level, ropes: Vec<Rope>) -> Self {
+        Self {
+            touched_targets: vec![0; level.targets.len()].into_boxed_slice(),
+            touching_targets: vec![None; ropes.len()].into_boxed_slice(),
+            ropes,
+            time: 0.0,
+            reference_energy: Vec::new(),
+        }
+    }
+
+    /// How many times each of the targets was hit
+    pub fn touched_targets(&self) -> &[usize] {
+        &self.touched_targets
+    }
+
+    pub fn all_targets_hit(&self) -> bool {
+        self.touched_targets.iter().all(|&t| t > 0)
+    }
+
+    /// Fraction of the targets that were hit at least once
+    pub fn progress(&self) -> f64 {
+        if self.touched_targets.is_empty() {
+            return 1.0;
+        }
+        let hit = self.touched_targets.iter().filter(|&&t| t > 0).count();
+        hit as f64 / self.touched_targets.len() as f64
+    }
+
+    /// Every hit of a target after the first one is a bonus
+    pub fn bonuses(&self) -> usize {
+        self.touched_targets.iter().map(|&t| t.saturating_sub(1)).sum()
+    }
+
+    pub fn is_broken(&self) -> bool {
+        self.ropes.iter().any(Rope::is_broken)
+    }
+
+    /// Total energy the ropes had when the run started
+    pub fn reference_energy(&self) -> f64 {
+        self.reference_energy.iter().sum()
+    }
+
+    pub fn energy(&self, level: &Level) -> Energy {
+        let time = self.time;
+        self.ropes.iter()
+            .map(|rope| rope.energy(|pos| level.gravity.potential(pos, time), TIME_STEP))
+            .fold(Energy::default(), |acc, e| acc + e)
+    }
+
+    /// Advances the simulation by one fixed time step
+    pub fn step(&mut self, level: &Level, control: Control) -> StepResult {
+        let time = self.time;
+        let potential = |pos| level.gravity.potential(pos, time);
+
+        if self.reference_energy.is_empty() {
+            self.reference_energy = self.ropes.iter()
+                .map(|rope| rope.energy(potential, TIME_STEP).total())
+                .collect();
+        }
+
+        for (rope, pendulum) in self.ropes.iter_mut().zip(&level.pendulums) {
+            if let Some(drive) = &pendulum.drive {
+                // sampled at the start of the step, same as the gravity and the forces
+                rope.move_root(pendulum.init_state.root + drive.offset(time));
+            } else if let Some(rail) = &pendulum.rail {
+                let max_step = ROOT_SPEED * TIME_STEP;
+                let target = control.steering.unwrap_or(rope.root + control.arrows * max_step);
+                let step = rail.clamp(target) - rope.root;
+                let step_length = step.magnitude();
+                if step_length > max_step {
+                    rope.move_root(rope.root + step * (max_step / step_length));
+                } else {
+                    rope.move_root(rope.root + step);
+                }
+            }
+            let accel = |pos, velocity| level.accel(pos, velocity, time);
+            match level.solver {
+                Solver::Relaxation => rope.simulate(accel, &level.obstacles, TIME_STEP, 15),
+                Solver::Exact => rope.simulate_exact(accel, TIME_STEP, 8),
+            }
+        }
+        self.time += TIME_STEP;
+
+        if !self.ropes.iter().all(Rope::is_finite) {
+            return StepResult::FellApart;
+        }
+
+        if level.conserve_energy {
+            let time = self.time;
+            let potential = |pos| level.gravity.potential(pos, time);
+            for (rope, &reference) in self.ropes.iter_mut().zip(&self.reference_energy) {
+                rope.correct_energy(reference, potential, TIME_STEP);
+            }
+        }
+
+        let mut result = StepResult::Nothing;
+        for (p, rope) in self.ropes.iter().enumerate() {
+            let tail = rope.tail();
+            let mut touching_target = None;
+            for (i, target) in level.targets.iter().enumerate() {
+                if target.accepts(p) && target.zone.contains(tail) {
+                    if self.touching_targets[p].is_none() {
+                        self.touched_targets[i] += 1;
+                        result = StepResult::Hit;
+                    }
+                    touching_target = Some(i);
+                }
+            }
+            self.touching_targets[p] = touching_target;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::{test_levels::swinging_level, Pendulum};
+
+    #[test]
+    fn entering_a_target_is_a_single_hit() {
+        let level = swinging_level();
+        let mut sim = Simulation::new(&level, level.init_state());
+        let first = (0..120)
+            .map(|_| sim.step(&level, Control::default()))
+            .find(|&result| result != StepResult::Nothing);
+        assert_eq!(first, Some(StepResult::Hit));
+        assert_eq!(sim.touched_targets(), &[1]);
+        assert!(sim.all_targets_hit());
+        assert_eq!(sim.progress(), 1.0);
+
+        // still inside of the target, so it does not count again
+        assert_eq!(sim.step(&level, Control::default()), StepResult::Nothing);
+        assert_eq!(sim.touched_targets(), &[1]);
+        assert_eq!(sim.bonuses(), 0);
+    }
+
+    #[test]
+    fn targets_of_other_pendulums_are_not_hit() {
+        let mut level = swinging_level();
+        let mut other = Rope::new(V2::new(1000.0, 0.0));
+        other.add(V2::new(1000.0, 100.0));
+        level.pendulums.push(Pendulum::new(other));
+        level.targets[0].pendulum = Some(1);
+
+        let mut sim = Simulation::new(&level, level.init_state());
+        for _ in 0..120 {
+            assert_eq!(sim.step(&level, Control::default()), StepResult::Nothing);
+        }
+        assert_eq!(sim.touched_targets(), &[0]);
+        assert_eq!(sim.progress(), 0.0);
+    }
+
+    #[test]
+    fn steps_are_fixed_and_deterministic() {
+        let level = swinging_level();
+        let mut a = Simulation::new(&level, level.init_state());
+        let mut b = Simulation::new(&level, level.init_state());
+        for _ in 0..100 {
+            a.step(&level, Control::default());
+            b.step(&level, Control::default());
+        }
+        assert!((a.time - 100.0 * TIME_STEP).abs() < 1e-9);
+        assert_eq!(a.ropes[0].tail(), b.ropes[0].tail());
+    }
+
+    #[test]
+    fn non_finite_state_falls_apart() {
+        let level = swinging_level();
+        let mut sim = Simulation::new(&level, level.init_state());
+        sim.ropes[0].move_root(V2::new(f64::NAN, 0.0));
+        assert_eq!(sim.step(&level, Control::default()), StepResult::FellApart);
+    }
+}
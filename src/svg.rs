@@ -0,0 +1,229 @@
+use std::{borrow::Cow, f64::consts::PI, fmt::Write};
+
+use ld_game_engine::V2;
+
+/// Makes the text safe to put into an attribute value or between the tags
+fn escape(text: &str) -> Cow<'_, str> {
+    if !text.contains(&['"', '<', '>', '&'][..]) {
+        return Cow::Borrowed(text);
+    }
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("&quot;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// How a shape is drawn, nothing is filled or stroked by default
+#[derive(Debug, Copy, Clone)]
+pub struct Style<'a> {
+    pub stroke: Option<&'a str>,
+    pub fill: Option<&'a str>,
+    pub width: f64,
+    pub opacity: f64,
+    pub dash: Option<&'a [f64]>,
+}
+
+impl Default for Style<'_> {
+    fn default() -> Self {
+        Self {
+            stroke: None,
+            fill: None,
+            width: 1.0,
+            opacity: 1.0,
+            dash: None,
+        }
+    }
+}
+
+impl<'a> Style<'a> {
+    pub fn stroke(color: &'a str, width: f64) -> Self {
+        Self {
+            stroke: Some(color),
+            width,
+            ..Self::default()
+        }
+    }
+
+    pub fn fill(color: &'a str) -> Self {
+        Self {
+            fill: Some(color),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_fill(mut self, color: &'a str) -> Self {
+        self.fill = Some(color);
+        self
+    }
+
+    pub fn with_opacity(mut self, opacity: f64) -> Self {
+        self.opacity = opacity;
+        self
+    }
+
+    pub fn with_dash(mut self, dash: &'a [f64]) -> Self {
+        self.dash = Some(dash);
+        self
+    }
+
+    fn write(&self, out: &mut String) {
+        write!(
+            out, r#" stroke="{}" fill="{}""#,
+            escape(self.stroke.unwrap_or("none")), escape(self.fill.unwrap_or("none")),
+        ).unwrap();
+        if self.stroke.is_some() {
+            write!(out, r#" stroke-width="{}" stroke-linecap="round""#, self.width).unwrap();
+        }
+        if self.opacity < 1.0 {
+            write!(out, r#" opacity="{:.3}""#, self.opacity).unwrap();
+        }
+        if let Some(dash) = self.dash.filter(|d| !d.is_empty()) {
+            let dash = dash.iter().map(ToString::to_string).collect::<Vec<_>>();
+            write!(out, r#" stroke-dasharray="{}""#, dash.join(" ")).unwrap();
+        }
+    }
+}
+
+/// Builds an SVG image in world coordinates, shapes are drawn in the order they are added
+#[derive(Debug, Clone)]
+pub struct Svg {
+    out: String,
+    /// How many of the clip paths and patterns there are, so that each gets its own id
+    ids: usize,
+}
+
+impl Svg {
+    pub fn new(top_left: V2, size: V2) -> Self {
+        let mut out = String::new();
+        write!(
+            out,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}" width="{}" height="{}">"#,
+            top_left.x, top_left.y, size.x, size.y, size.x.round(), size.y.round(),
+        ).unwrap();
+        out.push('\n');
+        Self { out, ids: 0 }
+    }
+
+    pub fn rect(&mut self, top_left: V2, size: V2, style: Style) {
+        write!(self.out, r#"<rect x="{:.2}" y="{:.2}" width="{:.2}" height="{:.2}""#, top_left.x, top_left.y, size.x, size.y).unwrap();
+        self.end(style);
+    }
+
+    pub fn line(&mut self, from: V2, to: V2, style: Style) {
+        write!(self.out, r#"<line x1="{:.2}" y1="{:.2}" x2="{:.2}" y2="{:.2}""#, from.x, from.y, to.x, to.y).unwrap();
+        self.end(style);
+    }
+
+    pub fn circle(&mut self, pos: V2, radius: f64, style: Style) {
+        write!(self.out, r#"<circle cx="{:.2}" cy="{:.2}" r="{:.2}""#, pos.x, pos.y, radius).unwrap();
+        self.end(style);
+    }
+
+    /// Clockwise from the start angle to the end one, same as on the canvas
+    pub fn arc(&mut self, center: V2, radius: f64, start: f64, end: f64, style: Style) {
+        let from = center + V2::new(start.cos(), start.sin()) * radius;
+        let to = center + V2::new(end.cos(), end.sin()) * radius;
+        let large = if end - start > PI { 1 } else { 0 };
+        write!(
+            self.out, r#"<path d="M {:.2} {:.2} A {:.2} {:.2} 0 {} 1 {:.2} {:.2}""#,
+            from.x, from.y, radius, radius, large, to.x, to.y,
+        ).unwrap();
+        self.end(style);
+    }
+
+    /// Centered at the given position, in the same monospace font as the game
+    pub fn text(&mut self, pos: V2, size: f64, text: &str, color: &str) {
+        writeln!(
+            self.out, r#"<text x="{:.2}" y="{:.2}" font-size="{}" font-family="monospace" text-anchor="middle" fill="{}">{}</text>"#,
+            pos.x, pos.y, size, escape(color), escape(text),
+        ).unwrap();
+    }
+
+    /// An image stretched over the given area, usually a data URL
+    pub fn image(&mut self, top_left: V2, size: V2, href: &str) {
+        writeln!(
+            self.out, r#"<image x="{:.2}" y="{:.2}" width="{:.2}" height="{:.2}" preserveAspectRatio="none" href="{}"/>"#,
+            top_left.x, top_left.y, size.x, size.y, escape(href),
+        ).unwrap();
+    }
+
+    /// Whatever is drawn inside is cut to the circle
+    pub fn clipped(&mut self, center: V2, radius: f64, draw: impl FnOnce(&mut Svg)) {
+        let shape = format!(r#"<circle cx="{:.2}" cy="{:.2}" r="{:.2}"/>"#, center.x, center.y, radius);
+        self.clip_group(&shape, draw);
+    }
+
+    /// Whatever is drawn inside is cut to the area between two circles around the same center
+    pub fn clipped_ring(&mut self, center: V2, inner: f64, outer: f64, draw: impl FnOnce(&mut Svg)) {
+        let mut shape = String::from(r#"<path clip-rule="evenodd" d=""#);
+        for &radius in &[outer, inner] {
+            write!(
+                shape, "M {0:.2} {1:.2} A {2:.2} {2:.2} 0 1 1 {3:.2} {1:.2} A {2:.2} {2:.2} 0 1 1 {0:.2} {1:.2} Z ",
+                center.x + radius, center.y, radius, center.x - radius,
+            ).unwrap();
+        }
+        shape.push_str(r#""/>"#);
+        self.clip_group(&shape, draw);
+    }
+
+    pub fn polyline(&mut self, points: impl IntoIterator<Item = V2>, style: Style) {
+        self.out.push_str(r#"<polyline points=""#);
+        for (i, pos) in points.into_iter().enumerate() {
+            if i > 0 {
+                self.out.push(' ');
+            }
+            write!(self.out, "{:.2},{:.2}", pos.x, pos.y).unwrap();
+        }
+        self.out.push('"');
+        self.end(style);
+    }
+
+    pub fn finish(mut self) -> String {
+        self.out.push_str("</svg>\n");
+        self.out
+    }
+
+    fn clip_group(&mut self, shape: &str, draw: impl FnOnce(&mut Svg)) {
+        self.ids += 1;
+        writeln!(self.out, r#"<clipPath id="clip{0}">{1}</clipPath><g clip-path="url(#clip{0})">"#, self.ids, shape).unwrap();
+        draw(self);
+        self.out.push_str("</g>\n");
+    }
+
+    fn end(&mut self, style: Style) {
+        style.write(&mut self.out);
+        self.out.push_str("/>\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attributes_and_text_are_escaped() {
+        let mut svg = Svg::new(V2::new(0.0, 0.0), V2::new(10.0, 10.0));
+        svg.circle(V2::new(5.0, 5.0), 1.0, Style::fill(r#"red" onload="x"#));
+        svg.text(V2::new(5.0, 5.0), 1.0, "<b> & co", "white");
+        let out = svg.finish();
+        assert!(out.contains(r#"fill="red&quot; onload=&quot;x""#));
+        assert!(out.contains("&lt;b&gt; &amp; co</text>"));
+    }
+
+    #[test]
+    fn clip_groups_are_closed() {
+        let mut svg = Svg::new(V2::new(0.0, 0.0), V2::new(10.0, 10.0));
+        svg.clipped(V2::new(5.0, 5.0), 2.0, |svg| svg.line(V2::new(0.0, 0.0), V2::new(10.0, 10.0), Style::stroke("white", 1.0)));
+        svg.clipped_ring(V2::new(5.0, 5.0), 1.0, 2.0, |_| {});
+        let out = svg.finish();
+        assert_eq!(out.matches("<g ").count(), out.matches("</g>").count());
+        assert!(out.contains(r#"id="clip1""#) && out.contains(r#"id="clip2""#));
+    }
+}
@@ -0,0 +1,204 @@
+use std::{collections::VecDeque, fmt::Write};
+
+use serde::*;
+
+use ld_game_engine::V2;
+
+use crate::{
+    level::Level,
+    rope::Energy,
+    sim::{Simulation, TIME_STEP},
+};
+
+#[derive(Debug, Copy, Clone, Serialize)]
+pub struct Joint {
+    pub pos: V2,
+    pub velocity: V2,
+}
+
+/// The state of a run after a single step
+#[derive(Debug, Clone, Serialize)]
+pub struct Frame {
+    pub time: f64,
+    /// All of the points of each of the ropes, starting with the root
+    pub joints: Vec<Vec<Joint>>,
+    /// Angle of each of the segments from the downward vertical, counter-clockwise in radians
+    pub angles: Vec<Vec<f64>>,
+    pub energy: Energy,
+    /// Targets that were entered on this step
+    pub hits: Vec<usize>,
+}
+
+/// Per-step data of a run, for analysing it outside of the game
+#[derive(Debug, Clone, Default)]
+pub struct Trajectory {
+    frames: VecDeque<Frame>,
+    /// Only this many of the latest steps are kept if set
+    limit: Option<usize>,
+    touched_targets: Vec<usize>,
+}
+
+impl Trajectory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limit(limit: usize) -> Self {
+        Self {
+            limit: Some(limit),
+            ..Self::default()
+        }
+    }
+
+    pub fn frames(&self) -> &VecDeque<Frame> {
+        &self.frames
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.touched_targets.clear();
+    }
+
+    /// Forgets the steps after the given state of the run, when it was taken back
+    pub fn rewind(&mut self, sim: &Simulation) {
+        while self.frames.back().map_or(false, |frame| frame.time > sim.time + TIME_STEP / 2.0) {
+            self.frames.pop_back();
+        }
+        self.touched_targets.clear();
+        self.touched_targets.extend_from_slice(sim.touched_targets());
+    }
+
+    /// Should be called after each of the steps of the simulation
+    pub fn record(&mut self, sim: &Simulation, level: &Level) {
+        let touched_targets = sim.touched_targets();
+        self.touched_targets.resize(touched_targets.len(), 0);
+        let hits = touched_targets.iter()
+            .zip(&self.touched_targets)
+            .enumerate()
+            .filter(|(_, (now, before))| now > before)
+            .map(|(i, _)| i)
+            .collect();
+        self.touched_targets.copy_from_slice(touched_targets);
+
+        self.frames.push_back(Frame {
+            time: sim.time,
+            joints: sim.ropes.iter()
+                .map(|rope| rope.points().iter()
+                    .map(|point| Joint {
+                        pos: point.pos(),
+                        velocity: point.velocity(TIME_STEP),
+                    })
+                    .collect())
+                .collect(),
+            angles: sim.ropes.iter()
+                .map(|rope| (0..rope.constraints.len())
+                    .map(|i| rope.segment_phase(i, TIME_STEP).0)
+                    .collect())
+                .collect(),
+            energy: sim.energy(level),
+            hits,
+        });
+        if let Some(limit) = self.limit {
+            while self.frames.len() > limit {
+                self.frames.pop_front();
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.frames).expect("frames are always serializable")
+    }
+
+    /// One row per step, the columns are taken from the first one,
+    /// hits are the indices of the entered targets separated by spaces
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("time");
+        if let Some(first) = self.frames.front() {
+            for (r, joints) in first.joints.iter().enumerate() {
+                for i in 0..joints.len() {
+                    write!(csv, ",rope{0}_joint{1}_x,rope{0}_joint{1}_y,rope{0}_joint{1}_vx,rope{0}_joint{1}_vy", r, i).unwrap();
+                }
+            }
+            for (r, angles) in first.angles.iter().enumerate() {
+                for i in 0..angles.len() {
+                    write!(csv, ",rope{}_segment{}_angle", r, i).unwrap();
+                }
+            }
+        }
+        csv.push_str(",kinetic,potential,total,hits\n");
+
+        for frame in &self.frames {
+            write!(csv, "{}", frame.time).unwrap();
+            for joint in frame.joints.iter().flatten() {
+                write!(csv, ",{},{},{},{}", joint.pos.x, joint.pos.y, joint.velocity.x, joint.velocity.y).unwrap();
+            }
+            for angle in frame.angles.iter().flatten() {
+                write!(csv, ",{}", angle).unwrap();
+            }
+            let hits = frame.hits.iter().map(ToString::to_string).collect::<Vec<_>>();
+            writeln!(
+                csv, ",{},{},{},{}",
+                frame.energy.kinetic, frame.energy.potential, frame.energy.total(), hits.join(" "),
+            ).unwrap();
+        }
+        csv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::Control;
+
+    fn record(trajectory: &mut Trajectory, steps: usize) -> Simulation {
+        let level = Level::tutorial_level();
+        let mut sim = Simulation::new(&level, level.init_state());
+        for _ in 0..steps {
+            sim.step(&level, Control::default());
+            trajectory.record(&sim, &level);
+        }
+        sim
+    }
+
+    #[test]
+    fn csv_has_a_row_per_step_with_all_of_the_columns() {
+        let mut trajectory = Trajectory::new();
+        record(&mut trajectory, 10);
+        let csv = trajectory.to_csv();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 11);
+        // a rope of two points with a single segment
+        let columns = lines[0].split(',').count();
+        assert_eq!(columns, 1 + 2 * 4 + 1 + 4);
+        assert!(lines.iter().all(|line| line.split(',').count() == columns));
+    }
+
+    #[test]
+    fn limit_keeps_the_latest_steps() {
+        let mut trajectory = Trajectory::with_limit(5);
+        let sim = record(&mut trajectory, 10);
+        assert_eq!(trajectory.frames().len(), 5);
+        assert_eq!(trajectory.frames().back().unwrap().time, sim.time);
+    }
+
+    #[test]
+    fn rewind_forgets_the_later_steps() {
+        let level = Level::tutorial_level();
+        let mut trajectory = Trajectory::new();
+        let mut sim = Simulation::new(&level, level.init_state());
+        let mut snapshot = None;
+        for i in 0..10 {
+            sim.step(&level, Control::default());
+            trajectory.record(&sim, &level);
+            if i == 3 {
+                snapshot = Some(sim.clone());
+            }
+        }
+        trajectory.rewind(&snapshot.unwrap());
+        assert_eq!(trajectory.frames().len(), 4);
+    }
+}